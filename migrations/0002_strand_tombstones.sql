@@ -0,0 +1,8 @@
+-- Migration number: 0002 	 2026-10-17T09:12:41.508Z
+
+-- Strands that have been removed from the spool.
+-- Kept so that a removed strand cannot be registered again.
+CREATE TABLE IF NOT EXISTS StrandTombstones (
+  cid BINARY(82) PRIMARY KEY,
+  removed_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,4 @@
+-- Migration number: 0003 	 2026-10-17T11:40:18.226Z
+
+-- Used when searching strands by spec
+CREATE INDEX IF NOT EXISTS idx_strands_spec ON Strands (spec);
@@ -0,0 +1,5 @@
+-- Migration number: 0004 	 2026-10-17T12:31:05.904Z
+
+-- Why and when a strand was made read only
+ALTER TABLE Strands ADD COLUMN frozen_reason TEXT;
+ALTER TABLE Strands ADD COLUMN frozen_at TIMESTAMP;
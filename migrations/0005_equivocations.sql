@@ -0,0 +1,20 @@
+-- Migration number: 0005 	 2026-10-17T14:02:47.318Z
+
+-- Validly signed tixels that conflict with one already stored at the same index
+CREATE TABLE IF NOT EXISTS Equivocations (
+  cid BINARY(82) PRIMARY KEY,
+  strand INTEGER NOT NULL,
+  idx INTEGER NOT NULL,
+  data BLOB NOT NULL,
+  detected_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+  -- the tixel stored at the same index when the conflict was found, kept here
+  -- so the evidence outlives it
+  stored_cid BINARY(82) NOT NULL,
+  stored_data BLOB NOT NULL,
+
+  FOREIGN KEY (strand) REFERENCES Strands(id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_equivocations_strand ON Equivocations (strand, idx);
+
+ALTER TABLE Strands ADD COLUMN equivocated BOOLEAN DEFAULT 0 NOT NULL;
@@ -0,0 +1,24 @@
+-- Migration number: 0006 	 2026-10-17T15:20:09.664Z
+
+-- Head and size of each strand, kept up to date as tixels are written
+CREATE TABLE IF NOT EXISTS StrandStats (
+  strand INTEGER PRIMARY KEY,
+  latest_idx INTEGER NOT NULL,
+  latest_cid BINARY(82) NOT NULL,
+  tixel_count INTEGER NOT NULL,
+  total_bytes INTEGER NOT NULL,
+  first_write_at TIMESTAMP,
+  last_write_at TIMESTAMP,
+
+  FOREIGN KEY (strand) REFERENCES Strands(id)
+);
+
+-- Write times of existing tixels are unknown
+INSERT OR IGNORE INTO StrandStats (strand, latest_idx, latest_cid, tixel_count, total_bytes)
+SELECT t.strand, t.idx, t.cid, c.tixel_count, c.total_bytes
+FROM Tixels t
+JOIN (
+  SELECT strand, MAX(idx) AS latest_idx, COUNT(*) AS tixel_count, SUM(LENGTH(data)) AS total_bytes
+  FROM Tixels
+  GROUP BY strand
+) c ON t.strand = c.strand AND t.idx = c.latest_idx;
@@ -0,0 +1,15 @@
+-- Migration number: 0007 	 2026-10-17T16:44:52.017Z
+
+-- Cross stitches from stored tixels to tixels of other strands.
+-- Targets are cids, as the strand stitched to may not be stored here.
+CREATE TABLE IF NOT EXISTS Stitches (
+  from_strand INTEGER NOT NULL,
+  from_idx INTEGER NOT NULL,
+  to_strand BINARY(82) NOT NULL,
+  to_tixel BINARY(82) NOT NULL,
+
+  PRIMARY KEY (from_strand, from_idx, to_strand),
+  FOREIGN KEY (from_strand) REFERENCES Strands(id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_stitches_to ON Stitches (to_strand, to_tixel);
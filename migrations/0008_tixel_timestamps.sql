@@ -0,0 +1,7 @@
+-- Migration number: 0008 	 2026-10-17T18:05:33.270Z
+
+-- Time taken from each tixel's payload at ingest, in milliseconds since the epoch.
+-- Left empty for tixels saved before this migration.
+ALTER TABLE Tixels ADD COLUMN timestamp_ms INTEGER;
+
+CREATE INDEX IF NOT EXISTS idx_tixels_timestamp ON Tixels (strand, timestamp_ms);
@@ -0,0 +1,6 @@
+-- Migration number: 0009 	 2026-10-17T19:37:58.841Z
+
+-- Large tixels keep their data in the blob store under blob_key, with data left empty.
+-- size is the length of the block wherever it is kept.
+ALTER TABLE Tixels ADD COLUMN blob_key TEXT;
+ALTER TABLE Tixels ADD COLUMN size INTEGER;
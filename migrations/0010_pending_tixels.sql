@@ -0,0 +1,19 @@
+-- Migration number: 0010 	 2026-10-17T20:52:16.307Z
+
+-- Verified tixels that arrived before the tixels they follow.
+-- They are moved into Tixels once the gap is filled, or dropped after expires_at
+-- when their strand next stages a tixel.
+CREATE TABLE IF NOT EXISTS PendingTixels (
+  cid BINARY(82) PRIMARY KEY,
+  strand INTEGER NOT NULL,
+  idx INTEGER NOT NULL,
+  data BLOB NOT NULL,
+  blob_key TEXT,
+  size INTEGER NOT NULL,
+  received_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+  expires_at TIMESTAMP NOT NULL,
+
+  FOREIGN KEY (strand) REFERENCES Strands(id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_pending_tixels_strand ON PendingTixels (strand, idx);
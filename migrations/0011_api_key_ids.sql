@@ -0,0 +1,8 @@
+-- Migration number: 0011 	 2026-10-17T21:40:09.615Z
+
+-- Keys are looked up by key_id and hashed with their own salt.
+-- Keys from before key ids keep working, and are given an id and rehashed when next used.
+ALTER TABLE ApiKeys ADD COLUMN key_id TEXT;
+ALTER TABLE ApiKeys ADD COLUMN peppered BOOLEAN DEFAULT 0 NOT NULL;
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_api_keys_key_id ON ApiKeys (key_id);
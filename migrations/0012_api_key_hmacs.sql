@@ -0,0 +1,5 @@
+-- Migration number: 0012 	 2026-10-17T22:18:44.072Z
+
+-- Fast keyed hash of each key's secret, checked instead of the scrypt hash.
+-- Filled in for existing keys the next time they are used.
+ALTER TABLE ApiKeys ADD COLUMN secret_hmac TEXT;
@@ -0,0 +1,5 @@
+-- Migration number: 0013 	 2026-10-17T22:51:30.118Z
+
+-- Usage of each key, written at most once per API_KEY_USAGE_INTERVAL
+ALTER TABLE ApiKeys ADD COLUMN request_count INTEGER DEFAULT 0 NOT NULL;
+ALTER TABLE ApiKeys ADD COLUMN bytes_written INTEGER DEFAULT 0 NOT NULL;
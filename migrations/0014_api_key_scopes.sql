@@ -0,0 +1,8 @@
+-- Migration number: 0014 	 2026-10-17T23:27:02.553Z
+
+-- What each key may do, comma separated, and the strands it may write tixels to
+ALTER TABLE ApiKeys ADD COLUMN scopes TEXT DEFAULT 'write-strands,write-tixels' NOT NULL;
+ALTER TABLE ApiKeys ADD COLUMN strands JSON;
+
+-- keys from before scopes could also remove twines
+UPDATE ApiKeys SET scopes = 'write-strands,write-tixels,remove';
@@ -0,0 +1,5 @@
+-- Migration number: 0015 	 2026-10-18T00:04:47.930Z
+
+-- Tixel writes per minute a strand may make without an api key,
+-- when not the SIGNED_WRITE_RATE_LIMIT default
+ALTER TABLE Strands ADD COLUMN write_rate_limit INTEGER;
@@ -4,6 +4,11 @@
 -- DROP TABLE IF EXISTS Strands;
 -- DROP TABLE IF EXISTS Registrations;
 -- DROP TABLE IF EXISTS ApiKeys;
+-- DROP TABLE IF EXISTS StrandTombstones;
+-- DROP TABLE IF EXISTS Equivocations;
+-- DROP TABLE IF EXISTS StrandStats;
+-- DROP TABLE IF EXISTS Stitches;
+-- DROP TABLE IF EXISTS PendingTixels;
 
 CREATE TABLE IF NOT EXISTS Strands (
   id INTEGER PRIMARY KEY AUTOINCREMENT,
@@ -12,16 +17,28 @@ CREATE TABLE IF NOT EXISTS Strands (
   spec TEXT NOT NULL,
   data BLOB NOT NULL,
   details JSON DEFAULT '{}',
-  writable BOOLEAN DEFAULT 1 NOT NULL
+  writable BOOLEAN DEFAULT 1 NOT NULL,
+  frozen_reason TEXT,
+  frozen_at TIMESTAMP,
+  equivocated BOOLEAN DEFAULT 0 NOT NULL,
+  -- Tixel writes per minute without an api key, SIGNED_WRITE_RATE_LIMIT if null
+  write_rate_limit INTEGER
 );
 
 CREATE INDEX IF NOT EXISTS idx_strands_cid ON Strands (cid);
+CREATE INDEX IF NOT EXISTS idx_strands_spec ON Strands (spec);
 
 CREATE TABLE IF NOT EXISTS Tixels (
   cid BINARY(82) UNIQUE NOT NULL,
   strand INTEGER NOT NULL,
   idx INTEGER NOT NULL,
   data BLOB NOT NULL,
+  -- Milliseconds since the epoch, from the payload
+  timestamp_ms INTEGER,
+  -- Set when data is kept in the blob store instead
+  blob_key TEXT,
+  -- Length of the block wherever it is kept
+  size INTEGER,
 
   -- Keys
   PRIMARY KEY (strand, idx),
@@ -29,6 +46,7 @@ CREATE TABLE IF NOT EXISTS Tixels (
 );
 
 CREATE INDEX IF NOT EXISTS idx_tixels_cid ON Tixels (cid);
+CREATE INDEX IF NOT EXISTS idx_tixels_timestamp ON Tixels (strand, timestamp_ms);
 
 CREATE TABLE IF NOT EXISTS Registrations (
   uuid TEXT PRIMARY KEY,
@@ -41,9 +59,93 @@ CREATE TABLE IF NOT EXISTS Registrations (
 -- Api Keys
 CREATE TABLE IF NOT EXISTS ApiKeys (
   id INTEGER PRIMARY KEY AUTOINCREMENT,
+  -- Public part of the key, unset for keys from before key ids until they are next used
+  key_id TEXT,
   description TEXT NOT NULL,
   hashed_key TEXT UNIQUE NOT NULL,
+  -- Whether API_KEY_PEPPER was mixed in to hashed_key
+  peppered BOOLEAN DEFAULT 0 NOT NULL,
+  -- Fast hash of the secret keyed by the pepper, checked instead of hashed_key
+  -- once set. Only kept for peppered keys.
+  secret_hmac TEXT,
   created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
   last_used_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
-  expires_at TIMESTAMP
+  expires_at TIMESTAMP,
+  -- Usage, written at most once per API_KEY_USAGE_INTERVAL
+  request_count INTEGER DEFAULT 0 NOT NULL,
+  bytes_written INTEGER DEFAULT 0 NOT NULL,
+  -- What the key may do, comma separated
+  scopes TEXT DEFAULT 'write-strands,write-tixels' NOT NULL,
+  -- Json array of strand cids the key may write tixels to, any if null
+  strands JSON
 );
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_api_keys_key_id ON ApiKeys (key_id);
+
+-- Removed strands
+CREATE TABLE IF NOT EXISTS StrandTombstones (
+  cid BINARY(82) PRIMARY KEY,
+  removed_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Validly signed tixels that conflict with one already stored at the same index
+CREATE TABLE IF NOT EXISTS Equivocations (
+  cid BINARY(82) PRIMARY KEY,
+  strand INTEGER NOT NULL,
+  idx INTEGER NOT NULL,
+  data BLOB NOT NULL,
+  detected_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+  -- the tixel stored at the same index when the conflict was found, kept here
+  -- so the evidence outlives it
+  stored_cid BINARY(82) NOT NULL,
+  stored_data BLOB NOT NULL,
+
+  FOREIGN KEY (strand) REFERENCES Strands(id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_equivocations_strand ON Equivocations (strand, idx);
+
+-- Head and size of each strand, kept up to date as tixels are written
+CREATE TABLE IF NOT EXISTS StrandStats (
+  strand INTEGER PRIMARY KEY,
+  latest_idx INTEGER NOT NULL,
+  latest_cid BINARY(82) NOT NULL,
+  tixel_count INTEGER NOT NULL,
+  total_bytes INTEGER NOT NULL,
+  first_write_at TIMESTAMP,
+  last_write_at TIMESTAMP,
+
+  FOREIGN KEY (strand) REFERENCES Strands(id)
+);
+
+-- Cross stitches from stored tixels to tixels of other strands.
+-- Targets are cids, as the strand stitched to may not be stored here.
+CREATE TABLE IF NOT EXISTS Stitches (
+  from_strand INTEGER NOT NULL,
+  from_idx INTEGER NOT NULL,
+  to_strand BINARY(82) NOT NULL,
+  to_tixel BINARY(82) NOT NULL,
+
+  PRIMARY KEY (from_strand, from_idx, to_strand),
+  FOREIGN KEY (from_strand) REFERENCES Strands(id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_stitches_to ON Stitches (to_strand, to_tixel);
+
+-- Verified tixels that arrived before the tixels they follow.
+-- They are moved into Tixels once the gap is filled, or dropped after expires_at
+-- when their strand next stages a tixel.
+CREATE TABLE IF NOT EXISTS PendingTixels (
+  cid BINARY(82) PRIMARY KEY,
+  strand INTEGER NOT NULL,
+  idx INTEGER NOT NULL,
+  data BLOB NOT NULL,
+  blob_key TEXT,
+  size INTEGER NOT NULL,
+  received_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+  expires_at TIMESTAMP NOT NULL,
+
+  FOREIGN KEY (strand) REFERENCES Strands(id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_pending_tixels_strand ON PendingTixels (strand, idx);
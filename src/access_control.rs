@@ -1,5 +1,4 @@
-use std::{fmt::Display, str::FromStr};
-use worker::*;
+use std::{collections::HashMap, fmt::Display, str::FromStr, sync::{Mutex, OnceLock}};
 
 use chrono::{NaiveDateTime, Utc};
 use rand::Rng;
@@ -13,101 +12,411 @@ use scrypt::{
     SaltString,
   }
 };
-use worker::D1Database;
+use ring::hmac;
+use twine_protocol::twine_lib::Cid;
+use crate::backend::{sql, SqlBackend};
 use crate::errors::ApiKeyValidationError;
 
-const SALT_STR : &str = "7IvnC9XW2D9FQrdEA/srAQ";
+/// Salt every key was hashed with before keys had ids.
+/// Only used to find the records of such keys until they are next used.
+const LEGACY_SALT_STR : &str = "7IvnC9XW2D9FQrdEA/srAQ";
+const KEY_PREFIX : &str = "spool_";
+const KEY_ID_BYTES : usize = 8;
+const SECRET_BYTES : usize = 32;
+/// How long a validated key is trusted without reading its record again.
+/// Expiry is still checked on every request.
+const KEY_CACHE_SECONDS : i64 = 30;
+/// Seconds between writes of a key's usage to its record, unless configured
+pub const DEFAULT_USAGE_INTERVAL_SECONDS : i64 = 60;
+/// Tixels a minute a strand may write without an api key, unless configured
+pub const DEFAULT_SIGNED_WRITE_RATE_LIMIT : u32 = 60;
 
+/// An api key, written `spool_<keyid>_<secret>`.
+///
+/// The key id finds the key's record and the secret is checked against its
+/// hash. Keys issued before key ids are bare hex secrets, and get an id
+/// derived from the secret.
 #[derive(Debug, Clone)]
-pub struct ApiKey(Vec<u8>);
+pub struct ApiKey {
+  id: String,
+  secret: Vec<u8>,
+  legacy: bool,
+}
 
 impl FromStr for ApiKey {
   type Err = hex::FromHexError;
 
   fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
-    let bytes = hex::decode(s)?;
-    Ok(ApiKey(bytes))
+    let (id, secret) = match s.strip_prefix(KEY_PREFIX) {
+      Some(rest) => rest.split_once('_').ok_or(hex::FromHexError::InvalidStringLength)?,
+      None => return Ok(Self::legacy(hex::decode(s)?)),
+    };
+    // the id is checked as hex so it's safe to show and log
+    hex::decode(id)?;
+    Ok(Self { id: id.to_lowercase(), secret: hex::decode(secret)?, legacy: false })
   }
 }
 
 impl Display for ApiKey {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-    let hex_str = hex::encode(&self.0);
-    write!(f, "{}", hex_str)
+    if self.legacy {
+      return write!(f, "{}", hex::encode(&self.secret));
+    }
+    write!(f, "{}{}_{}", KEY_PREFIX, self.id, hex::encode(&self.secret))
   }
 }
 
 impl ApiKey {
-  pub fn new(bytes: Vec<u8>) -> Self {
-    Self(bytes)
+  /// A key from before key ids, from its secret
+  pub fn legacy(secret: Vec<u8>) -> Self {
+    let digest = ring::digest::digest(&ring::digest::SHA256, &secret);
+    let id = hex::encode(&digest.as_ref()[..KEY_ID_BYTES]);
+    Self { id, secret, legacy: true }
   }
 
   pub fn generate() -> Self {
     let mut rng = rand::rng();
-    let mut bytes = [0u8; 32];
-    rng.fill(&mut bytes);
-    Self(bytes.to_vec())
+    let mut id = [0u8; KEY_ID_BYTES];
+    let mut secret = [0u8; SECRET_BYTES];
+    rng.fill(&mut id);
+    rng.fill(&mut secret);
+    Self { id: hex::encode(id), secret: secret.to_vec(), legacy: false }
+  }
+
+  /// Public part of the key, used to find its record
+  pub fn id(&self) -> &str {
+    &self.id
   }
 
   pub fn bytes(&self) -> &[u8] {
-    &self.0
+    &self.secret
+  }
+
+  pub fn is_legacy(&self) -> bool {
+    self.legacy
+  }
+
+  /// Secret as hashed, with the pepper appended if there is one
+  fn hash_input(&self, pepper: Option<&str>) -> Vec<u8> {
+    let mut input = self.secret.clone();
+    input.extend(pepper.unwrap_or_default().as_bytes());
+    input
+  }
+
+  /// Fast hash of the secret, keyed by the pepper.
+  ///
+  /// Only used with a pepper, since keys given to the admin api may not be
+  /// random enough to go without a slow hash when the hash is unkeyed.
+  fn hmac(&self, pepper: &str) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, pepper.as_bytes());
+    hex::encode(hmac::sign(&key, &self.secret))
+  }
+
+  /// Compare against a stored [`Self::hmac`] in constant time
+  fn hmac_matches(&self, pepper: &str, expected: &str) -> bool {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, pepper.as_bytes());
+    match hex::decode(expected) {
+      Ok(tag) => hmac::verify(&key, &self.secret, &tag).is_ok(),
+      Err(_) => false,
+    }
+  }
+
+  /// Check the key, returning what it may do
+  pub async fn validate(&self, db: &dyn SqlBackend, pepper: Option<&str>) -> std::result::Result<KeyGrant, ApiKeyValidationError> {
+    ApiKeyRecord::key_is_valid(db, self, pepper).await
+  }
+}
+
+/// Something an api key may be allowed to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Scope {
+  /// Save new strands
+  WriteStrands,
+  /// Save tixels, to the key's strands if it lists any
+  WriteTixels,
+  /// Register strands without waiting for approval
+  Register,
+  /// Remove strands and tixels, of the key's strands if it lists any
+  Remove,
+  /// Anything but registering strands
+  Admin,
+}
+
+impl Scope {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      Scope::WriteStrands => "write-strands",
+      Scope::WriteTixels => "write-tixels",
+      Scope::Register => "register",
+      Scope::Remove => "remove",
+      Scope::Admin => "admin",
+    }
+  }
+}
+
+impl FromStr for Scope {
+  type Err = String;
+
+  fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+    match s {
+      "write-strands" => Ok(Scope::WriteStrands),
+      "write-tixels" => Ok(Scope::WriteTixels),
+      "register" => Ok(Scope::Register),
+      "remove" => Ok(Scope::Remove),
+      "admin" => Ok(Scope::Admin),
+      _ => Err(format!("Unknown scope: {}", s)),
+    }
+  }
+}
+
+/// Scopes given to new keys unless others are asked for
+pub const DEFAULT_SCOPES : [Scope; 2] = [Scope::WriteStrands, Scope::WriteTixels];
+
+/// What a validated api key may do
+#[derive(Debug, Clone)]
+pub struct KeyGrant {
+  pub record_id: i64,
+  pub scopes: Vec<Scope>,
+  /// Strands the key may write tixels to, or any if unset
+  pub strands: Option<Vec<String>>,
+}
+
+impl KeyGrant {
+  /// Whether the key may act in a scope, and write to a strand if one is given
+  pub fn allows(&self, scope: Scope, strand: Option<&Cid>) -> bool {
+    // registering skips approval, so it is only given when asked for
+    if scope != Scope::Register && self.scopes.contains(&Scope::Admin) {
+      return true;
+    }
+    if !self.scopes.contains(&scope) {
+      return false;
+    }
+    match (strand, &self.strands) {
+      (Some(strand), Some(strands)) => strands.contains(&strand.to_string()),
+      _ => true,
+    }
   }
+}
 
-  pub async fn validate(&self, db: &D1Database) -> std::result::Result<(), ApiKeyValidationError> {
-    ApiKeyRecord::key_is_valid(db, self).await
+/// Marks a tixel write to a strand let through without an api key,
+/// to be accepted only if every tixel is of that strand and verifies
+/// against it, and the strand has a write slot for each verified tixel
+#[derive(Debug, Clone)]
+pub struct SignedWrite {
+  pub strand: Cid,
+  pub per_minute: u32,
+}
+
+/// Tixels written without an api key in this isolate, by strand,
+/// as the minute they were counted in and how many
+fn signed_write_windows() -> &'static Mutex<HashMap<Cid, (i64, u32)>> {
+  static WINDOWS: OnceLock<Mutex<HashMap<Cid, (i64, u32)>>> = OnceLock::new();
+  WINDOWS.get_or_init(Default::default)
+}
+
+/// Count `tixels` written to a strand without an api key, returning false
+/// and counting none of them if that would take the strand past `per_minute`
+/// this minute.
+///
+/// Tixels are counted per isolate, so this limits bursts rather than
+/// enforcing an exact rate.
+pub fn take_write_slots(strand: &Cid, per_minute: u32, tixels: u32) -> bool {
+  let minute = Utc::now().timestamp() / 60;
+  let mut windows = signed_write_windows().lock().unwrap();
+  let (window, count) = windows.entry(*strand).or_insert((minute, 0));
+  if *window != minute {
+    (*window, *count) = (minute, 0);
+  }
+  if count.saturating_add(tixels) > per_minute {
+    return false;
   }
+  *count += tixels;
+  true
+}
+
+/// Whether a strand has a write slot left this minute, without counting one
+pub fn has_write_slot(strand: &Cid, per_minute: u32) -> bool {
+  let minute = Utc::now().timestamp() / 60;
+  match signed_write_windows().lock().unwrap().get(strand) {
+    Some(&(window, count)) if window == minute => count < per_minute,
+    _ => per_minute > 0,
+  }
+}
+
+/// Hash a key secret with a fresh random salt
+fn hash_secret(input: &[u8]) -> String {
+  let mut salt = [0u8; 16];
+  rand::rng().fill(&mut salt);
+  let salt = SaltString::encode_b64(&salt).unwrap();
+  Scrypt.hash_password(input, &salt).unwrap().to_string()
+}
+
+/// What is needed to check a key again without reading its record
+struct CachedKey {
+  grant: KeyGrant,
+  /// Hmac of the secret keyed by [`cache_hmac_key`]
+  secret_tag: Vec<u8>,
+  peppered: bool,
+  expires_at: Option<NaiveDateTime>,
+  cached_at: NaiveDateTime,
+}
+
+/// Random key for the hmacs in [`key_cache`], so cached keys are checked
+/// quickly with or without a pepper. It never leaves the isolate.
+fn cache_hmac_key() -> &'static hmac::Key {
+  static KEY: OnceLock<hmac::Key> = OnceLock::new();
+  KEY.get_or_init(|| {
+    let mut bytes = [0u8; 32];
+    rand::rng().fill(&mut bytes);
+    hmac::Key::new(hmac::HMAC_SHA256, &bytes)
+  })
+}
+
+/// Keys validated recently in this isolate, by key id
+fn key_cache() -> &'static Mutex<HashMap<String, CachedKey>> {
+  static CACHE: OnceLock<Mutex<HashMap<String, CachedKey>>> = OnceLock::new();
+  CACHE.get_or_init(Default::default)
+}
+
+/// Use of a key in this isolate not yet written to its record
+#[derive(Default)]
+struct PendingUsage {
+  requests: u64,
+  bytes: u64,
+  flushed_at: Option<NaiveDateTime>,
+}
+
+fn pending_usage() -> &'static Mutex<HashMap<i64, PendingUsage>> {
+  static USAGE: OnceLock<Mutex<HashMap<i64, PendingUsage>>> = OnceLock::new();
+  USAGE.get_or_init(Default::default)
+}
+
+fn scopes_text(scopes: &[Scope]) -> String {
+  scopes.iter().map(Scope::as_str).collect::<Vec<_>>().join(",")
+}
+
+fn strands_json(strands: &[String]) -> String {
+  serde_json::to_string(strands).unwrap_or_default()
+}
+
+fn bool_from_int<'de, D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<bool, D::Error> {
+  Ok(i64::deserialize(deserializer)? != 0)
+}
+
+/// Scopes are stored comma separated
+fn scopes_from_text<'de, D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Vec<Scope>, D::Error> {
+  String::deserialize(deserializer)?
+    .split(',')
+    .filter(|s| !s.is_empty())
+    .map(|s| s.parse().map_err(serde::de::Error::custom))
+    .collect()
 }
 
+/// Strand lists are stored as json arrays
+fn strands_from_json<'de, D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Option<Vec<String>>, D::Error> {
+  match Option::<String>::deserialize(deserializer)? {
+    Some(json) => serde_json::from_str(&json).map_err(serde::de::Error::custom),
+    None => Ok(None),
+  }
+}
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ApiKeyRecord {
   pub id: i64,
+  /// Unset for keys from before key ids that haven't been used since
+  pub key_id: Option<String>,
   pub description: String,
   pub hashed_key: String,
+  /// Whether the pepper was mixed in to `hashed_key` and `secret_hmac`
+  #[serde(default, deserialize_with = "bool_from_int")]
+  pub peppered: bool,
+  /// Fast hash of the secret keyed by the pepper, checked instead of
+  /// `hashed_key` once set. Never set for keys hashed without a pepper.
+  #[serde(default, skip_serializing)]
+  pub secret_hmac: Option<String>,
   pub created_at: NaiveDateTime,
   pub last_used_at: NaiveDateTime,
   pub expires_at: Option<NaiveDateTime>,
+  /// Requests made with the key, as of its last usage write
+  #[serde(default)]
+  pub request_count: u64,
+  /// Request body bytes sent with the key, as of its last usage write
+  #[serde(default)]
+  pub bytes_written: u64,
+  #[serde(deserialize_with = "scopes_from_text")]
+  pub scopes: Vec<Scope>,
+  /// Strands the key may write tixels to, or any if unset
+  #[serde(default, deserialize_with = "strands_from_json")]
+  pub strands: Option<Vec<String>>,
 }
 
 impl ApiKeyRecord {
-  pub fn new<S: Into<String>>(api_key: &ApiKey, description: S, expires_at: Option<NaiveDateTime>) -> Self {
-    let bytes = api_key.bytes();
-    let salt = SaltString::from_b64(SALT_STR).unwrap();
-    let hashed_key = Scrypt.hash_password(&bytes, &salt).unwrap().to_string();
-
+  pub fn new<S: Into<String>>(api_key: &ApiKey, description: S, expires_at: Option<NaiveDateTime>, pepper: Option<&str>) -> Self {
     Self {
       id: -1,
+      key_id: Some(api_key.id().to_string()),
       description: description.into(),
-      hashed_key,
+      hashed_key: hash_secret(&api_key.hash_input(pepper)),
+      peppered: pepper.is_some(),
+      secret_hmac: pepper.map(|pepper| api_key.hmac(pepper)),
       created_at: Utc::now().naive_utc(),
       last_used_at: Utc::now().naive_utc(),
       expires_at,
+      request_count: 0,
+      bytes_written: 0,
+      scopes: DEFAULT_SCOPES.to_vec(),
+      strands: None,
     }
   }
 
-  pub async fn get(db: &D1Database, id: u64) -> std::result::Result<Option<ApiKeyRecord>, ApiKeyValidationError> {
+  pub async fn get(db: &dyn SqlBackend, id: u64) -> std::result::Result<Option<ApiKeyRecord>, ApiKeyValidationError> {
     let query_str = "SELECT * FROM ApiKeys WHERE id = ? LIMIT 1";
-    let query = query!(db, query_str, id)?;
-    let record: Option<ApiKeyRecord> = query.first(None).await?;
+    let query = sql!(db, query_str, id);
+    let record: Option<ApiKeyRecord> = db.first(query, None).await?;
     Ok(record)
   }
 
-  pub async fn delete(db: &D1Database, id: u64) -> std::result::Result<(), ApiKeyValidationError> {
+  /// Revoke a key. Isolates that checked it recently keep accepting it for
+  /// up to `KEY_CACHE_SECONDS`.
+  pub async fn delete(db: &dyn SqlBackend, id: u64) -> std::result::Result<(), ApiKeyValidationError> {
     let query_str = "DELETE FROM ApiKeys WHERE id = ?";
-    let query = query!(db, query_str, id)?;
-    query.run().await?;
+    let query = sql!(db, query_str, id);
+    db.run(query).await?;
+    // other isolates stop trusting the key within KEY_CACHE_SECONDS
+    key_cache().lock().unwrap().retain(|_, cached| cached.grant.record_id != id as i64);
     Ok(())
   }
 
-  pub async fn get_all(db: &D1Database) -> std::result::Result<Vec<ApiKeyRecord>, ApiKeyValidationError> {
+  /// Change what a key may do. Returns false if there is no such key.
+  ///
+  /// Isolates that checked the key recently keep its old scopes for up to
+  /// `KEY_CACHE_SECONDS`.
+  pub async fn set_scopes(db: &dyn SqlBackend, id: u64, scopes: &[Scope], strands: Option<&[String]>) -> std::result::Result<bool, ApiKeyValidationError> {
+    let query_str = "UPDATE ApiKeys SET scopes = ?, strands = ? WHERE id = ?";
+    let query = sql!(db, query_str, scopes_text(scopes), strands.map(strands_json), id);
+    let meta = db.run(query).await?;
+    // other isolates see the change within KEY_CACHE_SECONDS
+    key_cache().lock().unwrap().retain(|_, cached| cached.grant.record_id != id as i64);
+    Ok(meta.changes > 0)
+  }
+
+  pub fn grant(&self) -> KeyGrant {
+    KeyGrant {
+      record_id: self.id,
+      scopes: self.scopes.clone(),
+      strands: self.strands.clone(),
+    }
+  }
+
+  pub async fn get_all(db: &dyn SqlBackend) -> std::result::Result<Vec<ApiKeyRecord>, ApiKeyValidationError> {
     let query_str = "SELECT * FROM ApiKeys";
-    let query = query!(db, query_str);
-    let result = query.all().await?;
-    let records: Vec<ApiKeyRecord> = result.results()?;
+    let query = sql!(db, query_str);
+    let records: Vec<ApiKeyRecord> = db.all(query).await?;
     Ok(records)
   }
 
-  pub fn validate(&self, api_key: &ApiKey) -> std::result::Result<(), ApiKeyValidationError> {
+  pub fn validate(&self, api_key: &ApiKey, pepper: Option<&str>) -> std::result::Result<(), ApiKeyValidationError> {
     // check if the key is expired
     if let Some(expires_at) = self.expires_at {
       if expires_at < Utc::now().naive_utc() {
@@ -115,45 +424,319 @@ impl ApiKeyRecord {
       }
     }
 
-    let parsed_hash = PasswordHash::new(&self.hashed_key).unwrap();
-    if Scrypt.verify_password(&api_key.bytes(), &parsed_hash).is_ok() {
+    let pepper = if self.peppered { pepper } else { None };
+    if self.peppered && pepper.is_none() {
+      log::error!("Api key {} was hashed with a pepper, but none is configured", self.id);
+      return Err(ApiKeyValidationError::InvalidKey);
+    }
+    let valid = match (&self.secret_hmac, pepper) {
+      (Some(expected), Some(pepper)) => api_key.hmac_matches(pepper, expected),
+      _ => {
+        let parsed_hash = PasswordHash::new(&self.hashed_key).unwrap();
+        Scrypt.verify_password(&api_key.hash_input(pepper), &parsed_hash).is_ok()
+      },
+    };
+    if valid {
       Ok(())
     } else {
       Err(ApiKeyValidationError::InvalidKey)
     }
   }
 
-  pub async fn save(&mut self, db: &D1Database) -> std::result::Result<&mut Self, ApiKeyValidationError> {
+  /// Check a key against the cache, if it was validated recently
+  fn cached_validation(api_key: &ApiKey, pepper: Option<&str>) -> Option<std::result::Result<KeyGrant, ApiKeyValidationError>> {
+    let now = Utc::now().naive_utc();
+    let mut cache = key_cache().lock().unwrap();
+    let cached = cache.get(api_key.id())?;
+    // a newly configured pepper is mixed in by checking the record
+    if (now - cached.cached_at).num_seconds() >= KEY_CACHE_SECONDS || (pepper.is_some() && !cached.peppered) {
+      cache.remove(api_key.id());
+      return None;
+    }
+    if cached.expires_at.is_some_and(|expires_at| expires_at < now) {
+      return Some(Err(ApiKeyValidationError::ExpiredKey));
+    }
+    if hmac::verify(cache_hmac_key(), &api_key.secret, &cached.secret_tag).is_ok() {
+      Some(Ok(cached.grant.clone()))
+    } else {
+      Some(Err(ApiKeyValidationError::InvalidKey))
+    }
+  }
+
+  fn cache(&self, api_key: &ApiKey) {
+    let Some(key_id) = &self.key_id else {
+      return;
+    };
+    key_cache().lock().unwrap().insert(key_id.clone(), CachedKey {
+      grant: self.grant(),
+      secret_tag: hmac::sign(cache_hmac_key(), &api_key.secret).as_ref().to_vec(),
+      peppered: self.peppered,
+      expires_at: self.expires_at,
+      cached_at: Utc::now().naive_utc(),
+    });
+  }
+
+  /// Whether the record should be hashed again, to move it off the legacy
+  /// salt or to mix in a newly configured pepper
+  fn needs_rehash(&self, pepper: Option<&str>) -> bool {
+    self.key_id.is_none() || (pepper.is_some() && !self.peppered)
+  }
+
+  pub async fn save(&mut self, db: &dyn SqlBackend) -> std::result::Result<&mut Self, ApiKeyValidationError> {
+    if self.id != -1 {
+      let query_str = r#"
+      UPDATE ApiKeys SET key_id = ?, hashed_key = ?, peppered = ?, secret_hmac = ?, last_used_at = ?
+      WHERE id = ?;
+      "#;
+      let query = sql!(db, query_str, self.key_id, self.hashed_key, self.peppered, self.secret_hmac, self.last_used_at, self.id);
+      db.run(query).await?;
+      return Ok(self);
+    }
     let query_str = r#"
-    INSERT INTO ApiKeys (description, hashed_key, created_at, last_used_at, expires_at)
-    VALUES (?, ?, ?, ?, ?)
-    ON CONFLICT(hashed_key) DO UPDATE SET
-      last_used_at = excluded.last_used_at;
+    INSERT INTO ApiKeys (key_id, description, hashed_key, peppered, secret_hmac, created_at, last_used_at, expires_at, scopes, strands)
+    VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?);
     "#;
-    let query = query!(db, query_str, self.description, self.hashed_key, self.created_at, self.last_used_at, self.expires_at)?;
-    let meta = query.run().await?.meta()?.unwrap();
-    if self.id == -1 {
-      self.id = meta.last_row_id.unwrap_or(-1);
-    }
+    let query = sql!(
+      db,
+      query_str,
+      self.key_id,
+      self.description,
+      self.hashed_key,
+      self.peppered,
+      self.secret_hmac,
+      self.created_at,
+      self.last_used_at,
+      self.expires_at,
+      scopes_text(&self.scopes),
+      self.strands.as_deref().map(strands_json)
+    );
+    let meta = db.run(query).await?;
+    self.id = meta.last_row_id.unwrap_or(-1);
     Ok(self)
   }
 
-  pub async fn key_is_valid(db: &D1Database, api_key: &ApiKey) -> std::result::Result<(), ApiKeyValidationError> {
-    let hashed = Scrypt.hash_password(&api_key.bytes(), &SaltString::from_b64(SALT_STR).unwrap()).unwrap().to_string();
-    let query_str = "SELECT * FROM ApiKeys WHERE hashed_key = ? LIMIT 1";
-    let query = query!(db, query_str, hashed)?;
-    let record: Option<ApiKeyRecord> = query.first(None).await?;
+  async fn find(db: &dyn SqlBackend, api_key: &ApiKey) -> std::result::Result<Option<ApiKeyRecord>, ApiKeyValidationError> {
+    let query = sql!(db, "SELECT * FROM ApiKeys WHERE key_id = ? LIMIT 1", api_key.id());
+    if let Some(record) = db.first::<ApiKeyRecord>(query, None).await? {
+      return Ok(Some(record));
+    }
+    if !api_key.is_legacy() {
+      return Ok(None);
+    }
+    // a legacy key that hasn't been used since key ids were added
+    let salt = SaltString::from_b64(LEGACY_SALT_STR).unwrap();
+    let hashed = Scrypt.hash_password(api_key.bytes(), &salt).unwrap().to_string();
+    let query = sql!(db, "SELECT * FROM ApiKeys WHERE hashed_key = ? AND key_id IS NULL LIMIT 1", hashed);
+    Ok(db.first(query, None).await?)
+  }
+
+  pub async fn key_is_valid(db: &dyn SqlBackend, api_key: &ApiKey, pepper: Option<&str>) -> std::result::Result<KeyGrant, ApiKeyValidationError> {
+    if let Some(result) = Self::cached_validation(api_key, pepper) {
+      return result;
+    }
+    let mut rec = Self::find(db, api_key).await?.ok_or(ApiKeyValidationError::InvalidKey)?;
+    rec.validate(api_key, pepper)?;
+    let upgrade = rec.needs_rehash(pepper) || (rec.peppered && rec.secret_hmac.is_none());
+    if rec.needs_rehash(pepper) {
+      log::info!("Rehashing api key {} with its own salt", rec.id);
+      rec.key_id = Some(api_key.id().to_string());
+      rec.hashed_key = hash_secret(&api_key.hash_input(pepper));
+      rec.peppered = pepper.is_some();
+      rec.secret_hmac = None;
+    }
+    if rec.peppered && rec.secret_hmac.is_none() {
+      rec.secret_hmac = pepper.map(|pepper| api_key.hmac(pepper));
+    }
+    if upgrade {
+      rec.save(db).await?;
+    }
+    rec.cache(api_key);
+    Ok(rec.grant())
+  }
 
-    match record {
-      Some(mut rec) => {
-        rec.validate(api_key)?;
-        rec.last_used_at = Utc::now().naive_utc();
-        rec.save(db).await?;
-        Ok(())
-      }
-      None => {
-        Err(ApiKeyValidationError::InvalidKey)
+  /// Count a request made with a key in this isolate. Counts are written
+  /// to the key's record by [`Self::flush_usage`].
+  pub fn record_usage(id: i64, bytes: u64) {
+    let mut usage = pending_usage().lock().unwrap();
+    let pending = usage.entry(id).or_default();
+    pending.requests += 1;
+    pending.bytes += bytes;
+  }
+
+  /// Write the usage counted in this isolate to each key's record, on the
+  /// key's first use here and then at most once every `interval` seconds,
+  /// so most requests don't write at all. Counts that fail to be written
+  /// are kept for the next flush.
+  pub async fn flush_usage(db: &dyn SqlBackend, interval: i64) -> std::result::Result<(), ApiKeyValidationError> {
+    let now = Utc::now().naive_utc();
+    let due: Vec<_> = {
+      let mut usage = pending_usage().lock().unwrap();
+      usage.iter_mut()
+        .filter(|(_, pending)| pending.requests > 0)
+        .filter(|(_, pending)| pending.flushed_at.is_none_or(|at| (now - at).num_seconds() >= interval))
+        .map(|(id, pending)| {
+          let flushed_at = pending.flushed_at.replace(now);
+          (*id, std::mem::take(&mut pending.requests), std::mem::take(&mut pending.bytes), flushed_at)
+        })
+        .collect()
+    };
+    let query_str = r#"
+    UPDATE ApiKeys SET
+      last_used_at = ?,
+      request_count = request_count + ?,
+      bytes_written = bytes_written + ?
+    WHERE id = ?;
+    "#;
+    let mut result = Ok(());
+    for (id, requests, bytes, flushed_at) in due {
+      let query = sql!(db, query_str, now, requests, bytes, id);
+      if let Err(e) = db.run(query).await {
+        let mut usage = pending_usage().lock().unwrap();
+        let pending = usage.entry(id).or_default();
+        pending.requests += requests;
+        pending.bytes += bytes;
+        pending.flushed_at = flushed_at;
+        result = Err(e.into());
       }
     }
+    result
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::backend::{test_backend, SqliteBackend};
+  use futures::executor::block_on;
+
+  #[test]
+  fn saved_keys_validate() {
+    let db = test_backend();
+    let key = ApiKey::generate();
+    block_on(async {
+      let mut record = ApiKeyRecord::new(&key, "test", None, Some("pepper"));
+      record.save(&db).await.unwrap();
+      let stored = ApiKeyRecord::get(&db, record.id as u64).await.unwrap().unwrap();
+      assert_eq!(stored.key_id.as_deref(), Some(key.id()));
+      assert_eq!(stored.scopes, DEFAULT_SCOPES);
+      let grant = key.validate(&db, Some("pepper")).await.unwrap();
+      assert_eq!(grant.record_id, record.id);
+      assert!(stored.validate(&key, None).is_err());
+      let wrong: ApiKey = format!("{}{}_{}", KEY_PREFIX, key.id(), "00".repeat(SECRET_BYTES)).parse().unwrap();
+      assert!(matches!(wrong.validate(&db, Some("pepper")).await, Err(ApiKeyValidationError::InvalidKey)));
+    });
   }
-}
\ No newline at end of file
+
+  #[test]
+  fn legacy_keys_keep_working_and_get_an_id() {
+    let db = test_backend();
+    let key: ApiKey = hex::encode(ApiKey::generate().bytes()).parse().unwrap();
+    assert!(key.is_legacy());
+    block_on(async {
+      // as issued before key ids, hashed with the shared salt
+      let salt = SaltString::from_b64(LEGACY_SALT_STR).unwrap();
+      let mut record = ApiKeyRecord::new(&key, "legacy", None, None);
+      record.key_id = None;
+      record.hashed_key = Scrypt.hash_password(key.bytes(), &salt).unwrap().to_string();
+      record.save(&db).await.unwrap();
+
+      assert_eq!(key.validate(&db, Some("pepper")).await.unwrap().record_id, record.id);
+      let stored = ApiKeyRecord::get(&db, record.id as u64).await.unwrap().unwrap();
+      assert_eq!(stored.key_id.as_deref(), Some(key.id()));
+      assert!(stored.peppered);
+      assert_ne!(stored.hashed_key, record.hashed_key);
+      // found by its id from then on
+      key_cache().lock().unwrap().remove(key.id());
+      assert_eq!(key.validate(&db, Some("pepper")).await.unwrap().record_id, record.id);
+    });
+  }
+
+  #[test]
+  fn keys_without_a_pepper_keep_only_their_scrypt_hash() {
+    let db = test_backend();
+    let key = ApiKey::generate();
+    block_on(async {
+      let mut record = ApiKeyRecord::new(&key, "test", None, None);
+      assert!(record.secret_hmac.is_none());
+      record.save(&db).await.unwrap();
+      key.validate(&db, None).await.unwrap();
+      let stored = ApiKeyRecord::get(&db, record.id as u64).await.unwrap().unwrap();
+      assert!(stored.secret_hmac.is_none());
+      // a pepper configured later is mixed in, and only then is the hmac kept
+      key.validate(&db, Some("pepper")).await.unwrap();
+      let stored = ApiKeyRecord::get(&db, record.id as u64).await.unwrap().unwrap();
+      assert!(stored.peppered);
+      assert!(stored.secret_hmac.is_some());
+    });
+  }
+
+  #[test]
+  fn keys_without_a_pepper_are_cached() {
+    let db = test_backend();
+    let key = ApiKey::generate();
+    block_on(async {
+      let mut record = ApiKeyRecord::new(&key, "test", None, None);
+      record.save(&db).await.unwrap();
+      key.validate(&db, None).await.unwrap();
+      // gone from the database, but still trusted here for a while
+      db.run(sql!(&db, "DELETE FROM ApiKeys WHERE id = ?", record.id)).await.unwrap();
+      assert_eq!(key.validate(&db, None).await.unwrap().record_id, record.id);
+      let wrong: ApiKey = format!("{}{}_{}", KEY_PREFIX, key.id(), "00".repeat(SECRET_BYTES)).parse().unwrap();
+      assert!(matches!(wrong.validate(&db, None).await, Err(ApiKeyValidationError::InvalidKey)));
+    });
+  }
+
+  #[test]
+  fn usage_is_kept_until_written() {
+    // an id no saved key has, so other tests' requests aren't counted with it
+    let id = -7;
+    let pending = |id| pending_usage().lock().unwrap().get(&id).map(|p| (p.requests, p.bytes));
+    block_on(async {
+      ApiKeyRecord::record_usage(id, 10);
+      // a database without the table can't take the write
+      let broken = SqliteBackend::open_in_memory().unwrap();
+      assert!(ApiKeyRecord::flush_usage(&broken, 60).await.is_err());
+      assert_eq!(pending(id), Some((1, 10)));
+      ApiKeyRecord::record_usage(id, 5);
+      ApiKeyRecord::flush_usage(&test_backend(), 60).await.unwrap();
+      assert_eq!(pending(id), Some((0, 0)));
+    });
+  }
+
+  #[test]
+  fn admin_keys_dont_skip_registration() {
+    let grant = KeyGrant { record_id: 1, scopes: vec![Scope::Admin], strands: None };
+    assert!(grant.allows(Scope::Remove, None));
+    assert!(grant.allows(Scope::WriteStrands, None));
+    assert!(!grant.allows(Scope::Register, None));
+    let grant = KeyGrant { record_id: 1, scopes: DEFAULT_SCOPES.to_vec(), strands: None };
+    assert!(!grant.allows(Scope::Remove, None));
+  }
+
+  #[test]
+  fn write_slots_are_counted_by_tixel() {
+    let strand = Cid::default();
+    assert!(take_write_slots(&strand, 5, 3));
+    assert!(!take_write_slots(&strand, 5, 3));
+    assert!(take_write_slots(&strand, 5, 2));
+    assert!(!take_write_slots(&strand, 5, 1));
+  }
+
+  #[test]
+  fn scopes_can_be_changed() {
+    let db = test_backend();
+    let key = ApiKey::generate();
+    block_on(async {
+      let mut record = ApiKeyRecord::new(&key, "test", None, None);
+      record.save(&db).await.unwrap();
+      let strands = vec!["bafyexample".to_string()];
+      assert!(ApiKeyRecord::set_scopes(&db, record.id as u64, &[Scope::WriteTixels], Some(&strands)).await.unwrap());
+      let stored = ApiKeyRecord::get(&db, record.id as u64).await.unwrap().unwrap();
+      assert_eq!(stored.scopes, vec![Scope::WriteTixels]);
+      assert_eq!(stored.strands, Some(strands));
+      assert!(!ApiKeyRecord::set_scopes(&db, record.id as u64 + 1, &[], None).await.unwrap());
+      // ids past the range of a sqlite integer are refused rather than wrapped
+      assert!(ApiKeyRecord::set_scopes(&db, u64::MAX, &[], None).await.is_err());
+    });
+  }
+}
@@ -1,37 +1,55 @@
 use axum::extract::{State, Path, Json};
-use axum::routing::{get, post, delete};
+use axum::routing::{get, post, put, delete};
 use axum::Router;
+use crate::api_routes::parse_cid;
+use crate::app_state::AppState;
+
+/// Key and strand administration, served by the admin worker and the admin
+/// listener of a self hosted spool
+pub fn router() -> Router<AppState> {
+  api_keys::router().merge(strands::router())
+}
 
 pub mod api_keys {
   use chrono::Utc;
-  use serde::Deserialize;
+  use serde::{Deserialize, Serialize};
 
   use super::*;
-  use crate::{access_control::{ApiKey, ApiKeyRecord}, errors::ApiError, Env};
+  use crate::{access_control::{ApiKey, ApiKeyRecord, Scope, DEFAULT_SCOPES}, errors::ApiError};
 
-  pub fn router() -> Router<Env> {
+  pub fn router() -> Router<AppState> {
     Router::new()
       .route("/apikeys", get(list_keys))
       .route("/apikeys/{:id}", get(get_key))
       .route("/apikeys", post(create_key))
       .route("/apikeys/{:id}", delete(delete_key))
+      .route("/apikeys/{:id}/scopes", put(set_scopes))
+  }
+
+  /// Strand cids in their canonical form
+  fn parse_strands(strands: Option<Vec<String>>) -> std::result::Result<Option<Vec<String>>, ApiError> {
+    strands
+      .map(|strands| strands.iter()
+        .map(|cid| Ok(parse_cid(cid)?.to_string()))
+        .collect())
+      .transpose()
   }
 
   #[worker::send]
   pub async fn list_keys(
-    State(env): State<Env>,
+    State(state): State<AppState>,
   ) -> std::result::Result<Json<Vec<ApiKeyRecord>>, ApiError> {
-    let db = env.d1("DB")?;
-    Ok(Json(ApiKeyRecord::get_all(&db).await?))
+    let db = state.db.as_ref();
+    Ok(Json(ApiKeyRecord::get_all(db).await?))
   }
 
   #[worker::send]
   pub async fn get_key(
-    State(env): State<Env>,
+    State(state): State<AppState>,
     Path(id): Path<u64>,
   ) -> std::result::Result<Json<ApiKeyRecord>, ApiError> {
-    let db = env.d1("DB")?;
-    let record = ApiKeyRecord::get(&db, id).await?;
+    let db = state.db.as_ref();
+    let record = ApiKeyRecord::get(db, id).await?;
     if record.is_none() {
       return Err(ApiError::NotFound);
     }
@@ -40,38 +58,224 @@ pub mod api_keys {
 
   #[derive(Debug, Clone, Deserialize)]
   struct KeyPostData {
-    pub key: String,
+    /// An existing key to register. A new one is generated if missing.
+    pub key: Option<String>,
     pub description: String,
-    pub expires_at: Option<chrono::DateTime<Utc>>
+    pub expires_at: Option<chrono::DateTime<Utc>>,
+    /// Defaults to writing strands and tixels. `admin` allows anything but
+    /// `register`, which has to be given as well for a key to skip approval.
+    pub scopes: Option<Vec<Scope>>,
+    /// Strands the key may write tixels to, any if missing
+    pub strands: Option<Vec<String>>,
+  }
+
+  #[derive(Debug, Clone, Serialize)]
+  pub struct CreatedKey {
+    /// The generated key. It can't be recovered later.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    #[serde(flatten)]
+    pub record: ApiKeyRecord,
   }
 
   #[worker::send]
   pub async fn create_key(
-    State(env): State<Env>,
+    State(state): State<AppState>,
     Json(payload): Json<KeyPostData>
-  ) -> std::result::Result<Json<ApiKeyRecord>, ApiError> {
+  ) -> std::result::Result<Json<CreatedKey>, ApiError> {
     use std::str::FromStr;
-    let db = env.d1("DB")?;
-    let key = ApiKey::from_str(&payload.key).map_err(|e| ApiError::BadRequestData(e.to_string()))?;
+    let (key, generated) = match payload.key {
+      Some(key) => (ApiKey::from_str(&key).map_err(|e| ApiError::BadRequestData(e.to_string()))?, false),
+      None => (ApiKey::generate(), true),
+    };
     let mut record = ApiKeyRecord::new(
       &key,
       payload.description,
-      payload.expires_at.map(|d| d.naive_utc())
+      payload.expires_at.map(|d| d.naive_utc()),
+      state.api_key_pepper.as_deref()
     );
-    record.save(&db).await?;
-    log::info!("New API Key created: {}", record.description);
-    Ok(Json(record))
+    record.scopes = payload.scopes.unwrap_or(DEFAULT_SCOPES.to_vec());
+    record.strands = parse_strands(payload.strands)?;
+    record.save(state.db.as_ref()).await?;
+    log::info!("New API Key created: {} ({})", record.description, key.id());
+    Ok(Json(CreatedKey {
+      key: generated.then(|| key.to_string()),
+      record,
+    }))
   }
 
   #[worker::send]
   pub async fn delete_key(
-    State(env): State<Env>,
+    State(state): State<AppState>,
     Path(id): Path<u64>,
   ) -> std::result::Result<(), ApiError> {
-    let db = env.d1("DB")?;
-    ApiKeyRecord::delete(&db, id).await?;
+    let db = state.db.as_ref();
+    ApiKeyRecord::delete(db, id).await?;
     log::info!("API Key deleted: id {}", id);
     Ok(())
   }
 
-}
\ No newline at end of file
+  #[derive(Debug, Clone, Deserialize)]
+  struct ScopesPutData {
+    /// As for a new key, `admin` doesn't include `register`
+    pub scopes: Vec<Scope>,
+    pub strands: Option<Vec<String>>,
+  }
+
+  /// Replace what a key may do
+  #[worker::send]
+  pub async fn set_scopes(
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    Json(payload): Json<ScopesPutData>
+  ) -> std::result::Result<Json<ApiKeyRecord>, ApiError> {
+    let db = state.db.as_ref();
+    let strands = parse_strands(payload.strands)?;
+    if !ApiKeyRecord::set_scopes(db, id, &payload.scopes, strands.as_deref()).await? {
+      return Err(ApiError::NotFound);
+    }
+    log::info!("API Key scopes changed: id {}", id);
+    Ok(Json(ApiKeyRecord::get(db, id).await?.ok_or(ApiError::NotFound)?))
+  }
+
+}
+
+pub mod strands {
+  use serde::Deserialize;
+
+  use super::*;
+  use axum::extract::Query;
+  use crate::{d1_store::{PendingTixel, StrandStatus}, errors::ApiError};
+
+  pub fn router() -> Router<AppState> {
+    Router::new()
+      .route("/strands/{:cid}", get(get_status))
+      .route("/strands/{:cid}/freeze", post(freeze))
+      .route("/strands/{:cid}/unfreeze", post(unfreeze))
+      .route("/strands/{:cid}/rate-limit", put(set_rate_limit))
+      .route("/strands/{:cid}/reindex-stitches", post(reindex_stitches))
+      .route("/strands/{:cid}/pending", get(list_pending))
+      .route("/strands/{:cid}/pending", delete(purge_pending))
+  }
+
+  #[worker::send]
+  pub async fn get_status(
+    State(state): State<AppState>,
+    Path(cid): Path<String>,
+  ) -> std::result::Result<Json<StrandStatus>, ApiError> {
+    let store = state.store();
+    let status = store.strand_status(&parse_cid(&cid)?).await?;
+    Ok(Json(status.ok_or(ApiError::NotFound)?))
+  }
+
+  #[derive(Debug, Clone, Deserialize)]
+  struct FreezePostData {
+    pub reason: String,
+  }
+
+  #[worker::send]
+  pub async fn freeze(
+    State(state): State<AppState>,
+    Path(cid): Path<String>,
+    Json(payload): Json<FreezePostData>
+  ) -> std::result::Result<Json<StrandStatus>, ApiError> {
+    let cid = parse_cid(&cid)?;
+    let store = state.store();
+    if !store.freeze_strand(&cid, &payload.reason).await? {
+      return Err(ApiError::NotFound);
+    }
+    let status = store.strand_status(&cid).await?;
+    Ok(Json(status.ok_or(ApiError::NotFound)?))
+  }
+
+  #[worker::send]
+  pub async fn unfreeze(
+    State(state): State<AppState>,
+    Path(cid): Path<String>,
+  ) -> std::result::Result<Json<StrandStatus>, ApiError> {
+    let cid = parse_cid(&cid)?;
+    let store = state.store();
+    if !store.unfreeze_strand(&cid).await? {
+      return Err(ApiError::NotFound);
+    }
+    let status = store.strand_status(&cid).await?;
+    Ok(Json(status.ok_or(ApiError::NotFound)?))
+  }
+
+  #[derive(Debug, Clone, Deserialize)]
+  struct RateLimitPutData {
+    /// Unset to use the default
+    pub per_minute: Option<u32>,
+  }
+
+  /// Set how many tixel writes a minute the strand may make without an api key
+  #[worker::send]
+  pub async fn set_rate_limit(
+    State(state): State<AppState>,
+    Path(cid): Path<String>,
+    Json(payload): Json<RateLimitPutData>
+  ) -> std::result::Result<Json<StrandStatus>, ApiError> {
+    let cid = parse_cid(&cid)?;
+    let store = state.store();
+    if !store.set_write_rate_limit(&cid, payload.per_minute).await? {
+      return Err(ApiError::NotFound);
+    }
+    let status = store.strand_status(&cid).await?;
+    Ok(Json(status.ok_or(ApiError::NotFound)?))
+  }
+
+  #[derive(Debug, Clone, serde::Serialize)]
+  pub struct ReindexResult {
+    pub stitches: usize,
+  }
+
+  /// Index the stitches of tixels saved before stitches were indexed
+  #[worker::send]
+  pub async fn reindex_stitches(
+    State(state): State<AppState>,
+    Path(cid): Path<String>,
+  ) -> std::result::Result<Json<ReindexResult>, ApiError> {
+    let cid = parse_cid(&cid)?;
+    let store = state.store();
+    store.get_strand(&cid).await?;
+    let stitches = store.reindex_stitches(&cid).await?;
+    Ok(Json(ReindexResult { stitches }))
+  }
+
+  /// Tixels of a strand staged until the tixels before them arrive
+  #[worker::send]
+  pub async fn list_pending(
+    State(state): State<AppState>,
+    Path(cid): Path<String>,
+  ) -> std::result::Result<Json<Vec<PendingTixel>>, ApiError> {
+    let cid = parse_cid(&cid)?;
+    let store = state.store();
+    store.get_strand(&cid).await?;
+    Ok(Json(store.pending_tixels(&cid).await?))
+  }
+
+  #[derive(Debug, Clone, Default, Deserialize)]
+  struct PurgeParams {
+    #[serde(default)]
+    expired: bool,
+  }
+
+  #[derive(Debug, Clone, serde::Serialize)]
+  pub struct PurgeResult {
+    pub purged: usize,
+  }
+
+  /// Remove staged tixels of a strand, or only expired ones with `?expired=true`
+  #[worker::send]
+  pub async fn purge_pending(
+    State(state): State<AppState>,
+    Path(cid): Path<String>,
+    Query(params): Query<PurgeParams>,
+  ) -> std::result::Result<Json<PurgeResult>, ApiError> {
+    let cid = parse_cid(&cid)?;
+    let store = state.store();
+    store.get_strand(&cid).await?;
+    let purged = store.purge_pending(&cid, params.expired).await?;
+    Ok(Json(PurgeResult { purged }))
+  }
+}
@@ -0,0 +1,781 @@
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use std::collections::BTreeMap;
+use futures::stream::{iter, StreamExt};
+use serde::Serialize;
+use twine_protocol::prelude::*;
+use twine_protocol::twine_lib::{car::to_car_stream, twine::Tagged};
+use worker::send::{SendFuture, SendWrapper};
+
+use crate::{app_state::AppState, d1_store::StrandStats, errors::ApiError};
+
+/// Header carrying the next page cursor for CAR responses
+pub const NEXT_CURSOR_HEADER: HeaderName = HeaderName::from_static("x-next-cursor");
+/// Largest body a write reads whole, the same as the twine api allows
+pub const MAX_WRITE_BODY: usize = 1024 * 1024;
+
+pub fn wants_car(headers: &HeaderMap) -> bool {
+  let accepts = headers.get(header::ACCEPT)
+    .and_then(|v| v.to_str().ok())
+    .unwrap_or_default();
+  accepts == "application/octet-stream" || accepts == "application/vnd.ipld.car"
+}
+
+pub fn sends_car(headers: &HeaderMap) -> bool {
+  let content_type = headers.get(header::CONTENT_TYPE)
+    .and_then(|v| v.to_str().ok())
+    .unwrap_or_default();
+  content_type == "application/octet-stream" || content_type == "application/vnd.ipld.car"
+}
+
+#[derive(Serialize)]
+pub struct ListingData {
+  #[serde(with = "crate::dag_json")]
+  items: Vec<Tagged<AnyTwine>>,
+  /// Stats of listed strands, keyed by strand cid
+  #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+  stats: BTreeMap<String, StrandStats>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  next: Option<String>,
+}
+
+/// A cid sent by a client, refused as bad data if it doesn't parse
+pub fn parse_cid(cid: &str) -> Result<Cid, ApiError> {
+  Cid::try_from(cid).map_err(|e| ApiError::BadRequestData(e.to_string()))
+}
+
+/// Read a whole body, refusing it once it passes `limit` bytes
+pub async fn read_body(body: axum::body::Body, limit: usize) -> Result<axum::body::Bytes, ApiError> {
+  axum::body::to_bytes(body, limit).await.map_err(|e| {
+    match e.into_inner().downcast::<http_body_util::LengthLimitError>() {
+      Ok(_) => ApiError::PayloadTooLarge,
+      Err(e) => ApiError::BadRequestData(e.to_string()),
+    }
+  })
+}
+
+pub async fn car_bytes(items: Vec<AnyTwine>) -> Vec<u8> {
+  to_car_stream(iter(items), vec![Cid::default()]).concat().await
+}
+
+/// Respond with a page of twines as DAG-JSON, or as a CAR if requested
+pub async fn listing_response<T: Into<AnyTwine>>(headers: &HeaderMap, items: Vec<T>, next: Option<String>) -> Response {
+  let items: Vec<AnyTwine> = items.into_iter().map(|t| t.into()).collect();
+  if wants_car(headers) {
+    let mut res = (
+      [(header::CONTENT_TYPE, "application/vnd.ipld.car")],
+      car_bytes(items).await,
+    ).into_response();
+    if let Some(next) = next.and_then(|n| HeaderValue::from_str(&n).ok()) {
+      res.headers_mut().insert(NEXT_CURSOR_HEADER, next);
+    }
+    return res;
+  }
+  Json(ListingData {
+    items: items.into_iter().map(Tagged::from).collect(),
+    stats: BTreeMap::new(),
+    next,
+  }).into_response()
+}
+
+/// Like [`listing_response`], adding the stats of each strand to DAG-JSON listings
+pub async fn strand_listing_response(headers: &HeaderMap, entries: Vec<(Strand, Option<StrandStats>)>, next: Option<String>) -> Response {
+  if wants_car(headers) {
+    let strands: Vec<Strand> = entries.into_iter().map(|(s, _)| s).collect();
+    return listing_response(headers, strands, next).await;
+  }
+  let stats = entries.iter()
+    .filter_map(|(strand, stats)| Some((strand.cid().to_string(), stats.clone()?)))
+    .collect();
+  Json(ListingData {
+    items: entries.into_iter().map(|(s, _)| Tagged::from(AnyTwine::from(s))).collect(),
+    stats,
+    next,
+  }).into_response()
+}
+
+pub mod strands {
+  use std::{cell::RefCell, rc::Rc};
+  use axum::body::Body;
+  use axum::extract::{Path, Query, Request, State};
+  use futures::future::ready;
+  use futures::stream::{once, unfold};
+  use twine_protocol::twine_lib::resolver::AbsoluteRange;
+  use axum::middleware::Next;
+  use axum::routing::get;
+  use axum::Router;
+  use serde::Deserialize;
+
+  use super::*;
+  use crate::access_control::{has_write_slot, take_write_slots, SignedWrite};
+  use crate::car::{car_reader, car_twines};
+  use crate::d1_store::{verify_tixel, StitchRef, StrandSearch};
+  use crate::errors::{ErrorBody, WriteError};
+  use super::ingest::BlockOutcome;
+
+  /// Most `details.*` filters allowed in one search
+  const MAX_DETAIL_FILTERS: usize = 8;
+
+  pub fn router() -> Router<AppState> {
+    Router::new()
+      .route("/search", get(search))
+      .route("/equivocations/{strand}", get(equivocations))
+      .route("/stats/{strand}", get(stats))
+      .route("/stitches/to/{strand}", get(stitches_to))
+      .route("/stitches/from/{strand}/{index}", get(stitches_from))
+      .route("/export/{strand}", get(export))
+  }
+
+  #[derive(Debug, Clone, Deserialize)]
+  pub struct ListParams {
+    pub limit: Option<u64>,
+    pub after: Option<i64>,
+  }
+
+  /// Serves `GET /?limit=&after=` as a paged strand listing.
+  ///
+  /// Requests without paging parameters are passed through to the twine api.
+  pub async fn paged_listing(state: AppState, req: Request, next: Next) -> Response {
+    if req.method() != http::Method::GET || req.uri().path() != "/" {
+      return next.run(req).await;
+    }
+    let params = match Query::<ListParams>::try_from_uri(req.uri()) {
+      Ok(Query(params)) => params,
+      Err(e) => return e.into_response(),
+    };
+    if params.limit.is_none() && params.after.is_none() {
+      return next.run(req).await;
+    }
+    let limit = params.limit.unwrap_or(crate::d1_store::STRAND_PAGE_SIZE).clamp(1, state.max_batch_size);
+    let after = params.after.unwrap_or(0);
+
+    let page = SendFuture::new(async move {
+      Ok::<_, ApiError>(state.store().strands_page(after, limit).await?)
+    }).await;
+
+    match page {
+      Ok((strands, next)) => {
+        strand_listing_response(req.headers(), strands, next.map(|n| n.to_string())).await
+      },
+      Err(e) => e.into_response(),
+    }
+  }
+
+  /// Lets a tixel write (`PUT /{strand_cid}` with a CAR body) through
+  /// without an api key, if its strand is registered and approved and
+  /// has a write slot left. [`save_twines`] then only takes tixels of that
+  /// strand, if it has a slot for each one signed by it, and the store
+  /// refuses any that aren't.
+  pub async fn signed_write(state: AppState, mut req: Request, next: Next) -> Response {
+    let unauthorized = (http::StatusCode::UNAUTHORIZED, "Unauthorized").into_response();
+    if req.method() != http::Method::PUT || !sends_car(req.headers()) {
+      return unauthorized;
+    }
+    let cid = match Cid::try_from(req.uri().path().trim_start_matches('/')) {
+      Ok(cid) => cid,
+      Err(_) => return unauthorized,
+    };
+    let length = req.headers().get(header::CONTENT_LENGTH)
+      .and_then(|v| v.to_str().ok()?.parse::<usize>().ok());
+    if length.is_some_and(|length| length > MAX_WRITE_BODY) {
+      return ApiError::PayloadTooLarge.into_response();
+    }
+    let limit = SendFuture::new({
+      let state = state.clone();
+      async move { state.store().signed_write_limit(&cid).await }
+    }).await;
+    let per_minute = match limit {
+      Ok(Some(limit)) => limit.unwrap_or(state.signed_write_rate_limit),
+      Ok(None) => return unauthorized,
+      Err(e) => return ApiError::from(e).into_response(),
+    };
+    // slots are only taken for verified tixels, but strands already
+    // past their limit aren't read
+    if !has_write_slot(&cid, per_minute) {
+      return ApiError::TooManyWrites.into_response();
+    }
+    req.extensions_mut().insert(SignedWrite { strand: cid, per_minute });
+    next.run(req).await
+  }
+
+  /// Response to a write, with what came of each twine in the order sent
+  #[derive(Debug, Serialize)]
+  pub struct WriteSummary {
+    /// Why the first refused twine was refused
+    #[serde(flatten)]
+    pub refusal: Option<ErrorBody>,
+    pub outcomes: Vec<BlockOutcome>,
+  }
+
+  /// Serves `PUT /` with a CAR of strands, and `PUT /{strand_cid}` with a CAR
+  /// of that strand's tixels, in place of the twine api so that refusals are
+  /// answered with their own status rather than a `500`.
+  pub async fn save_twines(state: AppState, req: Request, next: Next) -> Response {
+    if req.method() != http::Method::PUT {
+      return next.run(req).await;
+    }
+    let strand_cid = match req.uri().path().trim_start_matches('/') {
+      "" => None,
+      path => match Cid::try_from(path) {
+        Ok(cid) => Some(cid),
+        Err(_) => return next.run(req).await,
+      },
+    };
+    let signed = req.extensions().get::<SignedWrite>().cloned();
+    if signed.as_ref().is_some_and(|signed| strand_cid != Some(signed.strand)) {
+      return ApiError::from(WriteError::Forbidden).into_response();
+    }
+    let saved = SendFuture::new(async move {
+      let bytes = read_body(req.into_body(), MAX_WRITE_BODY).await?;
+      let mut twines = Vec::new();
+      let mut blocks = Box::pin(car_twines(car_reader(&bytes[..]).await?));
+      while let Some(block) = blocks.next().await {
+        let twine = block?.1?;
+        match (&strand_cid, &twine) {
+          (None, AnyTwine::Strand(_)) => {},
+          (None, AnyTwine::Tixel(_)) => return Err(ApiError::BadRequestData("Not all items are strands".into())),
+          (Some(cid), AnyTwine::Tixel(t)) if t.strand_cid() == *cid => {},
+          (Some(_), _) => return Err(ApiError::BadRequestData("Not all items are tixels of the strand".into())),
+        }
+        twines.push(twine);
+      }
+      // only tixels the strand signed take its slots, so a flood of
+      // forgeries can't use them up
+      if let Some(signed) = signed {
+        let strand = state.store().get_strand(&signed.strand).await?;
+        let verified = twines.iter()
+          .filter(|twine| match twine {
+            AnyTwine::Tixel(t) => verify_tixel(&strand, t).is_ok(),
+            AnyTwine::Strand(_) => false,
+          })
+          .count();
+        let verified = u32::try_from(verified).unwrap_or(u32::MAX);
+        if !take_write_slots(&signed.strand, signed.per_minute, verified) {
+          return Err(ApiError::TooManyWrites);
+        }
+      }
+      Ok(state.store().save_batch(twines).await?)
+    }).await;
+    let report = match saved {
+      Ok(report) => report,
+      Err(e) => return e.into_response(),
+    };
+    let status = match &report.refusal {
+      Some(reason) => {
+        log::debug!("Write refused: {}", reason);
+        http::StatusCode::from_u16(reason.status()).unwrap_or(http::StatusCode::INTERNAL_SERVER_ERROR)
+      },
+      None => http::StatusCode::CREATED,
+    };
+    (status, Json(WriteSummary {
+      refusal: report.refusal.as_ref().map(ErrorBody::from),
+      outcomes: report.outcomes.into_iter()
+        .map(|(cid, outcome)| BlockOutcome { cid: cid.to_string(), outcome })
+        .collect(),
+    })).into_response()
+  }
+
+  /// Serves `DELETE /{cid}` for a strand, or for the latest tixel of its
+  /// strand, answering refusals with their own status.
+  pub async fn remove_twines(state: AppState, req: Request, next: Next) -> Response {
+    if req.method() != http::Method::DELETE {
+      return next.run(req).await;
+    }
+    let cid = match Cid::try_from(req.uri().path().trim_start_matches('/')) {
+      Ok(cid) => cid,
+      Err(_) => return next.run(req).await,
+    };
+    let removed = SendFuture::new(async move {
+      state.store().remove(&cid).await
+    }).await;
+    match removed {
+      Ok(()) => http::StatusCode::NO_CONTENT.into_response(),
+      Err(e) => ApiError::from(e).into_response(),
+    }
+  }
+
+  /// Search strands by spec and details.
+  ///
+  /// Accepts `spec`, `limit`, `after`, and any number of `details.<path>`
+  /// parameters, e.g. `/search?spec=twine/2.0.0&details.tags=weather`
+  #[worker::send]
+  pub async fn search(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(params): Query<Vec<(String, String)>>,
+  ) -> Result<Response, ApiError> {
+    let mut search = StrandSearch::default();
+    let mut limit = crate::d1_store::STRAND_PAGE_SIZE;
+    let mut after = 0;
+    for (key, value) in params {
+      match key.as_str() {
+        "spec" => search.spec = Some(value),
+        "limit" => limit = value.parse().map_err(|_| ApiError::InvalidQuery("Invalid limit".into()))?,
+        "after" => after = value.parse().map_err(|_| ApiError::InvalidQuery("Invalid cursor".into()))?,
+        _ => {
+          let path = key.strip_prefix("details.")
+            .filter(|path| is_valid_path(path))
+            .ok_or(ApiError::InvalidQuery(format!("Unknown search parameter: {}", key)))?;
+          search.details.push((path.to_string(), value));
+        },
+      }
+    }
+    if search.details.len() > MAX_DETAIL_FILTERS {
+      return Err(ApiError::InvalidQuery(format!("At most {} details filters allowed", MAX_DETAIL_FILTERS)));
+    }
+    let limit = limit.clamp(1, state.max_batch_size);
+    let (strands, next) = state.store().search_strands(&search, after, limit).await?;
+    Ok(strand_listing_response(&headers, strands, next.map(|n| n.to_string())).await)
+  }
+
+  /// Evidence that a strand author signed two tixels for one index.
+  ///
+  /// Lists the strand followed by each stored tixel and the tixel
+  /// that conflicts with it, so anyone can check the signatures.
+  #[worker::send]
+  pub async fn equivocations(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(strand): Path<String>,
+  ) -> Result<Response, ApiError> {
+    let strand_cid = parse_cid(&strand)?;
+    let store = state.store();
+    let strand = store.get_strand(&strand_cid).await?;
+    let mut items: Vec<AnyTwine> = vec![strand.into()];
+    for (stored, conflicting) in store.equivocations(&strand_cid).await? {
+      items.push(stored.into());
+      items.push(conflicting.into());
+    }
+    Ok(listing_response(&headers, items, None).await)
+  }
+
+  /// Head and size of a strand
+  #[worker::send]
+  pub async fn stats(
+    State(state): State<AppState>,
+    Path(strand): Path<String>,
+  ) -> Result<Json<StrandStats>, ApiError> {
+    let strand_cid = parse_cid(&strand)?;
+    // strands without tixels have no stats yet
+    let stats = state.store().strand_stats(&strand_cid).await?.ok_or(ApiError::NotFound)?;
+    Ok(Json(stats))
+  }
+
+  #[derive(Debug, Clone, Deserialize)]
+  pub struct StitchParams {
+    pub tixel: Option<String>,
+    pub limit: Option<u64>,
+    pub after: Option<i64>,
+  }
+
+  #[derive(Debug, Serialize)]
+  pub struct StitchPage {
+    items: Vec<StitchRef>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next: Option<String>,
+  }
+
+  /// Stored tixels that stitch to a strand, or to one of its tixels with `?tixel=`
+  #[worker::send]
+  pub async fn stitches_to(
+    State(state): State<AppState>,
+    Path(strand): Path<String>,
+    Query(params): Query<StitchParams>,
+  ) -> Result<Json<StitchPage>, ApiError> {
+    let strand_cid = parse_cid(&strand)?;
+    let tixel = params.tixel.as_deref().map(parse_cid).transpose()?;
+    let limit = params.limit.unwrap_or(crate::d1_store::STRAND_PAGE_SIZE).clamp(1, state.max_batch_size);
+    let (items, next) = state.store()
+      .stitches_to(&strand_cid, tixel.as_ref(), params.after.unwrap_or(0), limit)
+      .await?;
+    Ok(Json(StitchPage { items, next: next.map(|n| n.to_string()) }))
+  }
+
+  /// What a stored tixel stitches to
+  #[worker::send]
+  pub async fn stitches_from(
+    State(state): State<AppState>,
+    Path((strand, index)): Path<(String, u64)>,
+  ) -> Result<Json<Vec<StitchRef>>, ApiError> {
+    let strand_cid = parse_cid(&strand)?;
+    let store = state.store();
+    // an unknown tixel is not found, rather than stitching to nothing
+    store.get_tixel_by_index(&strand_cid, index).await?;
+    Ok(Json(store.stitches_from(&strand_cid, index).await?))
+  }
+
+  #[derive(Debug, Clone, Deserialize)]
+  pub struct ExportParams {
+    /// Index of the first tixel to include
+    pub from: Option<u64>,
+  }
+
+  /// Streams a whole strand as a CAR: the strand block, then its tixels in order.
+  ///
+  /// Tixels are read a batch at a time as the body is sent. An interrupted
+  /// export can be resumed with `?from=` set to the next index needed.
+  #[worker::send]
+  pub async fn export(
+    State(state): State<AppState>,
+    Path(strand): Path<String>,
+    Query(params): Query<ExportParams>,
+  ) -> Result<Response, ApiError> {
+    let strand_cid = parse_cid(&strand)?;
+    let store = state.store();
+    let strand = store.get_strand(&strand_cid).await?;
+    let latest = store.strand_stats(&strand_cid).await?.map(|s| s.latest_index);
+
+    let batches = unfold((store, params.from.unwrap_or(0)), move |(store, from)| async move {
+      let latest = latest?;
+      if from > latest {
+        return None;
+      }
+      let upper = latest.min(from.saturating_add(crate::d1_store::BATCH_SIZE - 1));
+      let batch = store.tixel_batch(&AbsoluteRange::new(strand_cid, from, upper)).await;
+      Some((batch, (store, upper + 1)))
+    });
+    let tixels = batches.flat_map(|batch| iter(match batch {
+      Ok(tixels) => tixels.into_iter().map(|t| t.map(AnyTwine::from)).collect(),
+      Err(e) => vec![Err(e)],
+    }));
+
+    // the CAR ends at the first failure, which is then passed on to abort the body
+    let failure = Rc::new(RefCell::new(None));
+    let twines = once(ready(AnyTwine::from(strand)))
+      .chain(tixels.scan(failure.clone(), |failure, twine| ready(match twine {
+        Ok(twine) => Some(twine),
+        Err(e) => {
+          *failure.borrow_mut() = Some(e);
+          None
+        },
+      })));
+    let trailer = once(async move { failure.borrow_mut().take() })
+      .filter_map(move |failure| ready(failure.map(|e| {
+        log::error!("Export of strand {} failed: {}", strand_cid, e);
+        Err(std::io::Error::other(e.to_string()))
+      })));
+    let body = to_car_stream(Box::pin(twines), vec![strand_cid])
+      .map(Ok::<_, std::io::Error>)
+      .chain(trailer);
+    // read through the send wrappers, like the handler itself
+    let body = unfold(SendWrapper::new(Box::pin(body)), |mut body| SendFuture::new(async move {
+      let chunk = body.next().await?;
+      Some((chunk, body))
+    }));
+
+    Ok((
+      [(header::CONTENT_TYPE, "application/vnd.ipld.car")],
+      Body::from_stream(body),
+    ).into_response())
+  }
+
+  /// Dotted paths of plain identifiers, like `subject` or `source.name`
+  fn is_valid_path(path: &str) -> bool {
+    path.split('.').all(|segment| {
+      !segment.is_empty() && segment.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    })
+  }
+}
+
+pub mod tixels {
+  use axum::extract::{Query, Request};
+  use axum::middleware::Next;
+  use chrono::{DateTime, Utc};
+  use serde::Deserialize;
+
+  use super::*;
+
+  #[derive(Debug, Clone, Deserialize)]
+  pub struct WindowParams {
+    pub limit: Option<u64>,
+    /// Index of the last tixel of the previous page
+    pub after: Option<u64>,
+  }
+
+  fn parse_time(time: &str) -> Result<DateTime<Utc>, ApiError> {
+    DateTime::parse_from_rfc3339(time)
+      .map(|t| t.with_timezone(&Utc))
+      .map_err(|e| ApiError::InvalidQuery(format!("Invalid time {}: {}", time, e)))
+  }
+
+  /// Serves time based queries on a strand:
+  ///
+  /// - `GET /<strand>@<RFC3339>` for the tixel at or before that instant
+  /// - `GET /<strand>@<RFC3339>..<RFC3339>` for the tixels within that window
+  ///
+  /// Other requests are passed through to the twine api.
+  pub async fn time_query(state: AppState, req: Request, next: Next) -> Response {
+    if req.method() != http::Method::GET {
+      return next.run(req).await;
+    }
+    let path = percent_encoding::percent_decode_str(req.uri().path().trim_start_matches('/'))
+      .decode_utf8_lossy()
+      .to_string();
+    let (strand, time) = match path.split_once('@') {
+      Some(parts) if !parts.0.contains('/') => parts,
+      _ => return next.run(req).await,
+    };
+    let params = match Query::<WindowParams>::try_from_uri(req.uri()) {
+      Ok(Query(params)) => params,
+      Err(e) => return e.into_response(),
+    };
+    let headers = req.headers().clone();
+    let strand = strand.to_string();
+    let time = time.to_string();
+
+    let result = SendFuture::new(async move {
+      let strand_cid = parse_cid(&strand)?;
+      let store = state.store();
+      match time.split_once("..") {
+        Some((from, to)) => {
+          let limit = params.limit.unwrap_or(state.max_batch_size).clamp(1, state.max_batch_size);
+          let (tixels, next) = store
+            .tixels_between(&strand_cid, parse_time(from)?, parse_time(to)?, params.after, limit)
+            .await?;
+          Ok::<_, ApiError>(listing_response(&headers, tixels, next.map(|n| n.to_string())).await)
+        },
+        None => {
+          let tixel = store.tixel_at_time(&strand_cid, parse_time(&time)?).await?;
+          Ok(listing_response(&headers, vec![tixel], None).await)
+        },
+      }
+    }).await;
+
+    result.unwrap_or_else(|e| e.into_response())
+  }
+}
+
+pub mod ingest {
+  use axum::body::Body;
+  use axum::extract::{Query, State};
+  use axum::routing::post;
+  use axum::Extension;
+  use axum::Router;
+  use std::pin::Pin;
+  use futures::stream::Stream;
+  use futures::TryStreamExt;
+  use serde::Deserialize;
+  use twine_protocol::twine_lib::errors::VerificationError;
+
+  use super::*;
+  use crate::car::{car_reader, car_twines, TwineBlockResult};
+  use crate::d1_store::{Outcome, RefusedStrands, WRITE_BATCH_SIZE};
+  use crate::access_control::{KeyGrant, Scope};
+  use crate::errors::{ErrorBody, WriteError};
+
+  /// Largest DAG-JSON ingest, which unlike a CAR is read whole
+  const MAX_JSON_BODY: usize = 8 * 1024 * 1024;
+
+  pub fn router() -> Router<AppState> {
+    Router::new()
+      .route("/ingest", post(ingest))
+  }
+
+  /// Which block outcomes to list in the response
+  #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+  #[serde(rename_all = "lowercase")]
+  pub enum OutcomeFilter {
+    #[default]
+    All,
+    Failed,
+    None,
+  }
+
+  #[derive(Debug, Default, Deserialize)]
+  pub struct IngestParams {
+    #[serde(default)]
+    outcomes: OutcomeFilter,
+  }
+
+  /// What came of one ingested block
+  #[derive(Debug, Serialize)]
+  pub struct BlockOutcome {
+    pub cid: String,
+    pub outcome: Outcome,
+  }
+
+  /// What came of the blocks in an ingest
+  #[derive(Debug, Default, Serialize)]
+  pub struct IngestSummary {
+    pub accepted: usize,
+    /// Tixels held until the tixels before them arrive
+    pub staged: usize,
+    /// Blocks that were already stored
+    pub duplicates: usize,
+    /// Blocks that aren't valid twines
+    pub invalid: usize,
+    /// Blocks the store would not take
+    pub refused: usize,
+    /// Tixels not attempted after a refused twine of their strand
+    pub skipped: usize,
+    /// Why the first refused twine was refused
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refusal: Option<ErrorBody>,
+    /// Why the body couldn't be read past the last block listed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failure: Option<String>,
+    /// Outcome of each block, in the order they were sent
+    pub outcomes: Vec<BlockOutcome>,
+    #[serde(skip)]
+    filter: OutcomeFilter,
+  }
+
+  impl IngestSummary {
+    fn record(&mut self, cid: String, outcome: Outcome) {
+      match outcome {
+        Outcome::Saved => self.accepted += 1,
+        Outcome::Staged => self.staged += 1,
+        Outcome::Duplicate => self.duplicates += 1,
+        Outcome::Invalid => self.invalid += 1,
+        Outcome::Refused(_) => self.refused += 1,
+        Outcome::Skipped => self.skipped += 1,
+      }
+      let listed = match self.filter {
+        OutcomeFilter::All => true,
+        OutcomeFilter::Failed => outcome.is_failure(),
+        OutcomeFilter::None => false,
+      };
+      if listed {
+        self.outcomes.push(BlockOutcome { cid, outcome });
+      }
+    }
+  }
+
+  /// Whether the key the request was made with may write a twine
+  fn allowed(grant: Option<&KeyGrant>, twine: &AnyTwine) -> bool {
+    let Some(grant) = grant else {
+      return true;
+    };
+    match twine {
+      AnyTwine::Strand(_) => grant.allows(Scope::WriteStrands, None),
+      AnyTwine::Tixel(t) => grant.allows(Scope::WriteTixels, Some(&t.strand_cid())),
+    }
+  }
+
+  #[derive(Deserialize)]
+  struct IngestItems {
+    items: Vec<serde_json::Value>,
+  }
+
+  /// The twines of a DAG-JSON body of the form `{ "items": [...] }`, each
+  /// with its cid as sent
+  fn json_twines(body: &[u8]) -> Result<Vec<TwineBlockResult>, ApiError> {
+    let IngestItems { items } = serde_json::from_slice(body)
+      .map_err(|e| ApiError::BadRequestData(e.to_string()))?;
+    Ok(items.into_iter()
+      .map(|item| {
+        let cid = item.get("cid")
+          .and_then(|cid| cid.get("/"))
+          .and_then(|cid| cid.as_str())
+          .unwrap_or_default()
+          .to_string();
+        let twine = crate::dag_json::deserialize::<_, Tagged<AnyTwine>>(item)
+          .map(|tagged| tagged.unpack())
+          .map_err(|e| VerificationError::General(e.to_string()));
+        (cid, twine)
+      })
+      .collect())
+  }
+
+  /// Ingest a batch of strands and tixels of any size as a CAR, or of up to
+  /// 8MB as DAG-JSON, and report what came of each block.
+  ///
+  /// CAR blocks are read from the body as they arrive and saved a batch at a
+  /// time with [`D1Store::save_batch_refusing`](crate::d1_store::D1Store::save_batch_refusing),
+  /// so neither the CAR nor the outcomes of every block are held in memory.
+  /// Once a twine is refused the later tixels of its strand are skipped, so a
+  /// client can retry exactly those that failed. `?outcomes=failed` lists only
+  /// those. A CAR that can't be read to the end is answered with `400` and
+  /// the summary of the blocks before the failure.
+  #[worker::send]
+  pub async fn ingest(
+    State(state): State<AppState>,
+    Query(params): Query<IngestParams>,
+    grant: Option<Extension<KeyGrant>>,
+    headers: HeaderMap,
+    body: Body,
+  ) -> Result<(http::StatusCode, Json<IngestSummary>), ApiError> {
+    let grant = grant.map(|Extension(grant)| grant);
+    let store = state.store();
+    let mut summary = IngestSummary { filter: params.outcomes, ..Default::default() };
+    let is_json = headers.get(header::CONTENT_TYPE)
+      .and_then(|v| v.to_str().ok())
+      .is_some_and(|v| v.starts_with("application/json"));
+
+    let blocks: Pin<Box<dyn Stream<Item = Result<TwineBlockResult, ApiError>>>> = if is_json {
+      let body = read_body(body, MAX_JSON_BODY).await?;
+      Box::pin(iter(json_twines(&body)?).map(Ok))
+    } else {
+      let body = body.into_data_stream()
+        .map_err(std::io::Error::other)
+        .into_async_read();
+      Box::pin(car_twines(car_reader(body).await?))
+    };
+
+    let mut refused = RefusedStrands::default();
+    let mut chunks = blocks.chunks(WRITE_BATCH_SIZE);
+    while let Some(chunk) = chunks.next().await {
+      // outcomes settled here in the order the blocks were sent, or None
+      // for the twines passed on to the store
+      let mut settled = Vec::with_capacity(chunk.len());
+      let mut twines = Vec::new();
+      for block in chunk {
+        let (cid, block) = match block {
+          Ok(block) => block,
+          Err(e) => {
+            summary.failure = Some(e.to_string());
+            break;
+          },
+        };
+        let outcome = match block {
+          Ok(twine) if refused.skips(&twine) => Some(Outcome::Skipped),
+          Ok(twine) if !allowed(grant.as_ref(), &twine) => {
+            refused.refuse(&twine);
+            Some(Outcome::Refused(WriteError::Forbidden.code()))
+          },
+          Ok(twine) => {
+            twines.push(twine);
+            None
+          },
+          Err(e) => {
+            log::debug!("Invalid block {} in ingest: {}", cid, e);
+            Some(Outcome::Invalid)
+          },
+        };
+        settled.push((cid, outcome));
+      }
+
+      let report = store.save_batch_refusing(twines, &mut refused).await?;
+      let mut refusal = report.refusal;
+      let mut saved = report.outcomes.into_iter();
+      for (cid, outcome) in settled {
+        let outcome = match outcome {
+          Some(outcome @ Outcome::Refused(_)) => {
+            summary.refusal.get_or_insert_with(|| ErrorBody::from(&WriteError::Forbidden));
+            outcome
+          },
+          Some(outcome) => outcome,
+          None => {
+            let outcome = saved.next().map(|(_, o)| o).unwrap_or(Outcome::Skipped);
+            // the first twine the store refused is the one it gave a reason for
+            if matches!(outcome, Outcome::Refused(_)) && summary.refusal.is_none() {
+              summary.refusal = refusal.take().as_ref().map(ErrorBody::from);
+            }
+            outcome
+          },
+        };
+        summary.record(cid, outcome);
+      }
+    }
+    log::info!(
+      "Ingested: {} accepted, {} staged, {} duplicates, {} invalid, {} refused, {} skipped",
+      summary.accepted, summary.staged, summary.duplicates, summary.invalid, summary.refused, summary.skipped
+    );
+    let status = match summary.failure {
+      Some(_) => http::StatusCode::BAD_REQUEST,
+      None => http::StatusCode::OK,
+    };
+    Ok((status, Json(summary)))
+  }
+}
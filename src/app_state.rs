@@ -0,0 +1,115 @@
+use std::sync::Arc;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::PathBuf;
+use axum::body::Body;
+use axum::response::IntoResponse;
+#[cfg(target_arch = "wasm32")]
+use http_body_util::BodyDataStream;
+#[cfg(target_arch = "wasm32")]
+use worker::Env;
+
+use crate::{backend::SqlBackend, d1_store::D1Store, errors::ApiError};
+use crate::blob_store::BlobStore;
+#[cfg(target_arch = "wasm32")]
+use crate::{backend::D1Backend, blob_store::R2BlobStore};
+
+/// Where static pages like `register.html` are served from
+#[derive(Clone)]
+pub enum Assets {
+  /// The worker's `ASSETS` binding
+  #[cfg(target_arch = "wasm32")]
+  Binding(Env),
+  /// A local directory
+  #[cfg(not(target_arch = "wasm32"))]
+  Directory(PathBuf),
+}
+
+impl Assets {
+  pub async fn fetch(&self, path: &str) -> Result<http::Response<Body>, ApiError> {
+    match self {
+      #[cfg(target_arch = "wasm32")]
+      Assets::Binding(env) => {
+        let url: String = ["https://dummyurl.com/", path].concat();
+        let res = env.assets("ASSETS")?.fetch(url, None).await?;
+        Ok(res.map(|b| Body::from_stream(BodyDataStream::new(b))))
+      },
+      #[cfg(not(target_arch = "wasm32"))]
+      Assets::Directory(dir) => {
+        let bytes = std::fs::read(dir.join(path)).map_err(|_| ApiError::NotFound)?;
+        let content_type = if path.ends_with(".html") {
+          "text/html; charset=utf-8"
+        } else {
+          "application/octet-stream"
+        };
+        Ok(([(http::header::CONTENT_TYPE, content_type)], bytes).into_response())
+      },
+    }
+  }
+}
+
+/// State shared by the spool routes, wherever they are running
+#[derive(Clone)]
+pub struct AppState {
+  pub db: Arc<dyn SqlBackend>,
+  pub max_batch_size: u64,
+  pub accept_all_strands: bool,
+  /// Payload field tixel times are read from, see [`D1Store::timestamp_field`]
+  pub timestamp_field: String,
+  /// Where large tixel data is kept, if anywhere
+  pub blobs: Option<Arc<dyn BlobStore>>,
+  pub blob_threshold: usize,
+  /// Secret mixed in to api key hashes, if any
+  pub api_key_pepper: Option<String>,
+  /// Seconds between writes of each api key's usage
+  pub key_usage_interval: i64,
+  /// Whether approved strands may write tixels without an api key
+  pub signed_writes: bool,
+  /// Tixels per minute a strand may write without an api key, unless set
+  /// for the strand
+  pub signed_write_rate_limit: u32,
+  pub assets: Assets,
+}
+
+impl AppState {
+  #[cfg(target_arch = "wasm32")]
+  pub fn from_env(env: &Env) -> worker::Result<Self> {
+    Ok(Self {
+      db: Arc::new(D1Backend::new(env.d1("DB")?)),
+      max_batch_size: crate::get_max_batch_size(env),
+      accept_all_strands: env.var("ACCEPT_ALL_STRANDS")
+        .map(|s| s.to_string())
+        .unwrap_or("false".to_string()) == "true",
+      timestamp_field: env.var("TIMESTAMP_FIELD")
+        .map(|s| s.to_string())
+        .unwrap_or(crate::d1_store::DEFAULT_TIMESTAMP_FIELD.to_string()),
+      blobs: env.bucket("BLOBS").ok()
+        .map(|bucket| Arc::new(R2BlobStore::new(bucket)) as Arc<dyn BlobStore>),
+      blob_threshold: env.var("BLOB_THRESHOLD")
+        .ok()
+        .and_then(|s| s.to_string().parse().ok())
+        .unwrap_or(crate::d1_store::DEFAULT_BLOB_THRESHOLD),
+      api_key_pepper: env.secret("API_KEY_PEPPER").ok().map(|s| s.to_string()),
+      key_usage_interval: env.var("API_KEY_USAGE_INTERVAL")
+        .ok()
+        .and_then(|s| s.to_string().parse().ok())
+        .unwrap_or(crate::access_control::DEFAULT_USAGE_INTERVAL_SECONDS),
+      signed_writes: env.var("SIGNED_WRITES")
+        .map(|s| s.to_string())
+        .unwrap_or("false".to_string()) == "true",
+      signed_write_rate_limit: env.var("SIGNED_WRITE_RATE_LIMIT")
+        .ok()
+        .and_then(|s| s.to_string().parse().ok())
+        .unwrap_or(crate::access_control::DEFAULT_SIGNED_WRITE_RATE_LIMIT),
+      assets: Assets::Binding(env.clone()),
+    })
+  }
+
+  pub fn store(&self) -> D1Store {
+    let store = D1Store::with_shared_backend(self.db.clone())
+      .with_timestamp_field(self.timestamp_field.clone());
+    match &self.blobs {
+      Some(blobs) => store.with_blob_store(blobs.clone(), self.blob_threshold),
+      None => store,
+    }
+  }
+}
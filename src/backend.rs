@@ -0,0 +1,289 @@
+use std::sync::Arc;
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+use serde::{de::DeserializeOwned, Serialize, Serializer};
+use twine_protocol::twine_lib::Cid;
+
+#[cfg(target_arch = "wasm32")]
+mod d1;
+#[cfg(target_arch = "wasm32")]
+pub use d1::D1Backend;
+#[cfg(any(feature = "sqlite", test))]
+mod sqlite;
+#[cfg(any(feature = "sqlite", test))]
+pub use sqlite::SqliteBackend;
+
+/// A row as returned by a backend, keyed by column name.
+///
+/// Blobs are represented as arrays of bytes, which is how D1 returns them.
+pub type Row = serde_json::Map<String, serde_json::Value>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum BackendError {
+  #[error("Database error: {0}")]
+  Database(String),
+  #[error("Problem decoding row: {0}")]
+  Decode(#[from] serde_json::Error),
+  #[error("Problem binding parameters: {0}")]
+  Bind(String),
+}
+
+impl From<worker::Error> for BackendError {
+  fn from(e: worker::Error) -> Self {
+    BackendError::Database(e.to_string())
+  }
+}
+
+/// A value that can be bound to a statement parameter
+#[derive(Debug, Clone, PartialEq)]
+pub enum SqlValue {
+  Null,
+  Integer(i64),
+  Real(f64),
+  Text(String),
+  Blob(Vec<u8>),
+}
+
+impl Serialize for SqlValue {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    match self {
+      SqlValue::Null => serializer.serialize_none(),
+      SqlValue::Integer(i) => serializer.serialize_i64(*i),
+      SqlValue::Real(f) => serializer.serialize_f64(*f),
+      SqlValue::Text(s) => serializer.serialize_str(s),
+      // sequences, to match how blobs have always been bound in D1
+      SqlValue::Blob(b) => serializer.collect_seq(b),
+    }
+  }
+}
+
+pub trait ToSqlValue {
+  fn to_sql_value(&self) -> Result<SqlValue, BackendError>;
+}
+
+impl<T: ToSqlValue + ?Sized> ToSqlValue for &T {
+  fn to_sql_value(&self) -> Result<SqlValue, BackendError> {
+    (**self).to_sql_value()
+  }
+}
+
+impl<T: ToSqlValue + ?Sized> ToSqlValue for Arc<T> {
+  fn to_sql_value(&self) -> Result<SqlValue, BackendError> {
+    (**self).to_sql_value()
+  }
+}
+
+impl<T: ToSqlValue> ToSqlValue for Option<T> {
+  fn to_sql_value(&self) -> Result<SqlValue, BackendError> {
+    match self {
+      Some(v) => v.to_sql_value(),
+      None => Ok(SqlValue::Null),
+    }
+  }
+}
+
+macro_rules! impl_to_sql_integer {
+  ($($t:ty),*) => {
+    $(impl ToSqlValue for $t {
+      fn to_sql_value(&self) -> Result<SqlValue, BackendError> {
+        Ok(SqlValue::Integer(i64::from(*self)))
+      }
+    })*
+  };
+}
+
+impl_to_sql_integer!(i64, i32, u32, u8, bool);
+
+/// Unsigned values too large for a sqlite integer are refused rather than wrapped
+macro_rules! impl_to_sql_unsigned {
+  ($($t:ty),*) => {
+    $(impl ToSqlValue for $t {
+      fn to_sql_value(&self) -> Result<SqlValue, BackendError> {
+        i64::try_from(*self)
+          .map(SqlValue::Integer)
+          .map_err(|_| BackendError::Bind(format!("{} is too large for an integer column", self)))
+      }
+    })*
+  };
+}
+
+impl_to_sql_unsigned!(u64, usize);
+
+impl ToSqlValue for f64 {
+  fn to_sql_value(&self) -> Result<SqlValue, BackendError> {
+    Ok(SqlValue::Real(*self))
+  }
+}
+
+impl ToSqlValue for str {
+  fn to_sql_value(&self) -> Result<SqlValue, BackendError> {
+    Ok(SqlValue::Text(self.to_string()))
+  }
+}
+
+impl ToSqlValue for String {
+  fn to_sql_value(&self) -> Result<SqlValue, BackendError> {
+    Ok(SqlValue::Text(self.clone()))
+  }
+}
+
+impl ToSqlValue for [u8] {
+  fn to_sql_value(&self) -> Result<SqlValue, BackendError> {
+    Ok(SqlValue::Blob(self.to_vec()))
+  }
+}
+
+impl ToSqlValue for Vec<u8> {
+  fn to_sql_value(&self) -> Result<SqlValue, BackendError> {
+    Ok(SqlValue::Blob(self.clone()))
+  }
+}
+
+impl ToSqlValue for Cid {
+  fn to_sql_value(&self) -> Result<SqlValue, BackendError> {
+    Ok(SqlValue::Blob(self.to_bytes()))
+  }
+}
+
+impl ToSqlValue for NaiveDateTime {
+  fn to_sql_value(&self) -> Result<SqlValue, BackendError> {
+    // same format as chrono's serde representation
+    Ok(SqlValue::Text(self.format("%Y-%m-%dT%H:%M:%S%.f").to_string()))
+  }
+}
+
+/// Deserialize a cid stored as a blob
+pub fn cid_from_bytes<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Cid, D::Error> {
+  let bytes: Vec<u8> = serde_bytes::deserialize(deserializer)?;
+  Cid::try_from(bytes).map_err(serde::de::Error::custom)
+}
+
+/// A query and its bound parameters
+#[derive(Debug, Clone)]
+pub struct Statement {
+  pub sql: String,
+  pub params: Vec<SqlValue>,
+  /// Why a parameter couldn't be bound, reported when the statement is run
+  bind_error: Option<String>,
+}
+
+impl Statement {
+  pub fn new<S: Into<String>>(sql: S) -> Self {
+    Self { sql: sql.into(), params: vec![], bind_error: None }
+  }
+
+  pub fn bind<V: ToSqlValue + ?Sized>(mut self, value: &V) -> Self {
+    match value.to_sql_value() {
+      Ok(value) => self.params.push(value),
+      Err(e) => {
+        self.bind_error.get_or_insert(e.to_string());
+        self.params.push(SqlValue::Null);
+      },
+    }
+    self
+  }
+
+  /// The bound parameters, or the first problem binding them
+  pub fn checked_params(&self) -> Result<&[SqlValue], BackendError> {
+    match &self.bind_error {
+      Some(e) => Err(BackendError::Bind(e.clone())),
+      None => Ok(&self.params),
+    }
+  }
+}
+
+/// Metadata from running a statement
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RunMeta {
+  pub changes: usize,
+  pub last_row_id: Option<i64>,
+}
+
+/// A SQLite flavoured database the spool can run against
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+pub trait SqlBackend: Send + Sync {
+  fn prepare(&self, sql: &str) -> Statement {
+    Statement::new(sql)
+  }
+
+  async fn rows(&self, statement: Statement) -> Result<Vec<Row>, BackendError>;
+
+  async fn run(&self, statement: Statement) -> Result<RunMeta, BackendError>;
+
+  /// Run all statements, in order, in a single transaction
+  async fn batch(&self, statements: Vec<Statement>) -> Result<Vec<RunMeta>, BackendError>;
+}
+
+impl<'a> dyn SqlBackend + 'a {
+  pub async fn all<T: DeserializeOwned>(&self, statement: Statement) -> Result<Vec<T>, BackendError> {
+    self.rows(statement).await?
+      .into_iter()
+      .map(|row| Ok(serde_json::from_value(serde_json::Value::Object(row))?))
+      .collect()
+  }
+
+  /// Get the first row, or a single column of it
+  pub async fn first<T: DeserializeOwned>(&self, statement: Statement, column: Option<&str>) -> Result<Option<T>, BackendError> {
+    let row = match self.rows(statement).await?.into_iter().next() {
+      Some(row) => row,
+      None => return Ok(None),
+    };
+    let value = match column {
+      Some(column) => row.get(column).cloned().unwrap_or(serde_json::Value::Null),
+      None => serde_json::Value::Object(row),
+    };
+    Ok(Some(serde_json::from_value(value)?))
+  }
+}
+
+/// Build a [`Statement`] for a backend, binding each argument in order.
+///
+/// Mirrors `worker::query!`.
+macro_rules! sql {
+  ($db:expr, $query:expr $(, $args:expr)* $(,)?) => {
+    $db.prepare($query)$(.bind(&$args))*
+  };
+}
+pub(crate) use sql;
+
+/// An in memory database with the current schema, for tests
+#[cfg(test)]
+pub(crate) fn test_backend() -> SqliteBackend {
+  let backend = SqliteBackend::open_in_memory().unwrap();
+  backend.execute_batch(include_str!("../schema.sql")).unwrap();
+  backend
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use futures::executor::block_on;
+
+  #[test]
+  fn refuses_bad_parameters() {
+    let db: &dyn SqlBackend = &test_backend();
+    block_on(async {
+      let too_many = sql!(db, "SELECT * FROM Strands WHERE id = ?", 1i64, 2i64);
+      assert!(matches!(db.rows(too_many).await, Err(BackendError::Bind(_))));
+      let too_few = sql!(db, "SELECT * FROM Strands WHERE id = ? AND cid = ?", 1i64);
+      assert!(matches!(db.rows(too_few).await, Err(BackendError::Bind(_))));
+      let too_large = sql!(db, "SELECT * FROM Strands WHERE id = ?", u64::MAX);
+      assert!(matches!(db.rows(too_large).await, Err(BackendError::Bind(_))));
+    });
+  }
+
+  #[test]
+  fn scripts_roll_back_with_their_statements() {
+    let backend = test_backend();
+    let db: &dyn SqlBackend = &backend;
+    backend.execute_batch("CREATE TABLE d1_migrations (id INTEGER PRIMARY KEY, name TEXT);").unwrap();
+    let script = "CREATE TABLE Extra (id INTEGER); SELECT * FROM Missing;";
+    let record = sql!(db, "INSERT INTO d1_migrations (name) VALUES (?1)", "0100_broken.sql");
+    assert!(backend.execute_batch_with(script, &[record]).is_err());
+    block_on(async {
+      assert!(db.rows(sql!(db, "SELECT * FROM Extra")).await.is_err());
+      assert!(db.rows(sql!(db, "SELECT * FROM d1_migrations")).await.unwrap().is_empty());
+    });
+  }
+}
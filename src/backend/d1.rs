@@ -0,0 +1,59 @@
+use async_trait::async_trait;
+use serde::Serialize;
+use worker::{send::SendFuture, D1Database, D1PreparedStatement, D1Result};
+
+use super::*;
+
+/// Backend for a Cloudflare D1 binding
+pub struct D1Backend(D1Database);
+
+impl D1Backend {
+  pub fn new(db: D1Database) -> Self {
+    Self(db)
+  }
+
+  fn statement(&self, statement: &Statement) -> Result<D1PreparedStatement, BackendError> {
+    let serializer = serde_wasm_bindgen::Serializer::new().serialize_missing_as_null(true);
+    let params = statement.checked_params()?.iter()
+      .map(|p| p.serialize(&serializer).map_err(|e| BackendError::Database(e.to_string())))
+      .collect::<Result<Vec<_>, _>>()?;
+    Ok(self.0.prepare(&statement.sql).bind(&params)?)
+  }
+}
+
+fn to_meta(result: &D1Result) -> Result<RunMeta, BackendError> {
+  let meta = result.meta()?;
+  Ok(RunMeta {
+    changes: meta.as_ref().and_then(|m| m.changes).unwrap_or(0),
+    last_row_id: meta.and_then(|m| m.last_row_id),
+  })
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl SqlBackend for D1Backend {
+  async fn rows(&self, statement: Statement) -> Result<Vec<Row>, BackendError> {
+    SendFuture::new(async move {
+      let result = self.statement(&statement)?.all().await?;
+      let rows = result.results::<Row>()?;
+      Ok(rows)
+    }).await
+  }
+
+  async fn run(&self, statement: Statement) -> Result<RunMeta, BackendError> {
+    SendFuture::new(async move {
+      let result = self.statement(&statement)?.run().await?;
+      to_meta(&result)
+    }).await
+  }
+
+  async fn batch(&self, statements: Vec<Statement>) -> Result<Vec<RunMeta>, BackendError> {
+    SendFuture::new(async move {
+      let statements = statements.iter()
+        .map(|s| self.statement(s))
+        .collect::<Result<Vec<_>, _>>()?;
+      let results = self.0.batch(statements).await?;
+      results.iter().map(to_meta).collect()
+    }).await
+  }
+}
@@ -0,0 +1,127 @@
+use std::path::Path;
+use std::sync::Mutex;
+use async_trait::async_trait;
+use rusqlite::{types::ValueRef, Connection};
+
+use super::*;
+
+/// Backend for a local SQLite database
+pub struct SqliteBackend(Mutex<Connection>);
+
+impl SqliteBackend {
+  pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, BackendError> {
+    Ok(Self::from_connection(Connection::open(path)?))
+  }
+
+  pub fn open_in_memory() -> Result<Self, BackendError> {
+    Ok(Self::from_connection(Connection::open_in_memory()?))
+  }
+
+  pub fn from_connection(conn: Connection) -> Self {
+    Self(Mutex::new(conn))
+  }
+
+  /// Run a script of several statements, like a schema migration
+  pub fn execute_batch(&self, sql: &str) -> Result<(), BackendError> {
+    Ok(self.conn()?.execute_batch(sql)?)
+  }
+
+  /// Run a script and then some statements, all in a single transaction
+  pub fn execute_batch_with(&self, sql: &str, statements: &[Statement]) -> Result<Vec<RunMeta>, BackendError> {
+    let mut conn = self.conn()?;
+    let tx = conn.transaction()?;
+    tx.execute_batch(sql)?;
+    let results = statements.iter()
+      .map(|s| run_one(&tx, s))
+      .collect::<Result<Vec<_>, _>>()?;
+    tx.commit()?;
+    Ok(results)
+  }
+
+  fn conn(&self) -> Result<std::sync::MutexGuard<'_, Connection>, BackendError> {
+    self.0.lock().map_err(|e| BackendError::Database(e.to_string()))
+  }
+}
+
+impl From<rusqlite::Error> for BackendError {
+  fn from(e: rusqlite::Error) -> Self {
+    BackendError::Database(e.to_string())
+  }
+}
+
+fn prepare<'c>(conn: &'c Connection, statement: &Statement) -> Result<rusqlite::Statement<'c>, BackendError> {
+  let mut stmt = conn.prepare(&statement.sql)?;
+  let params = statement.checked_params()?;
+  if params.len() != stmt.parameter_count() {
+    return Err(BackendError::Bind(format!(
+      "Statement takes {} parameters but {} were bound",
+      stmt.parameter_count(),
+      params.len()
+    )));
+  }
+  for (i, param) in params.iter().enumerate() {
+    let value = match param {
+      SqlValue::Null => rusqlite::types::Value::Null,
+      SqlValue::Integer(i) => rusqlite::types::Value::Integer(*i),
+      SqlValue::Real(f) => rusqlite::types::Value::Real(*f),
+      SqlValue::Text(s) => rusqlite::types::Value::Text(s.clone()),
+      SqlValue::Blob(b) => rusqlite::types::Value::Blob(b.clone()),
+    };
+    stmt.raw_bind_parameter(i + 1, value)?;
+  }
+  Ok(stmt)
+}
+
+fn to_json(value: ValueRef) -> serde_json::Value {
+  match value {
+    ValueRef::Null => serde_json::Value::Null,
+    ValueRef::Integer(i) => i.into(),
+    ValueRef::Real(f) => f.into(),
+    ValueRef::Text(t) => String::from_utf8_lossy(t).into(),
+    ValueRef::Blob(b) => b.iter().copied().collect(),
+  }
+}
+
+fn run_one(conn: &Connection, statement: &Statement) -> Result<RunMeta, BackendError> {
+  let mut stmt = prepare(conn, statement)?;
+  let changes = stmt.raw_execute()?;
+  Ok(RunMeta {
+    changes,
+    last_row_id: Some(conn.last_insert_rowid()),
+  })
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl SqlBackend for SqliteBackend {
+  async fn rows(&self, statement: Statement) -> Result<Vec<Row>, BackendError> {
+    let conn = self.conn()?;
+    let mut stmt = prepare(&conn, &statement)?;
+    let columns: Vec<String> = stmt.column_names().into_iter().map(String::from).collect();
+    let mut rows = stmt.raw_query();
+    let mut results = vec![];
+    while let Some(row) = rows.next()? {
+      let mut record = Row::new();
+      for (i, name) in columns.iter().enumerate() {
+        record.insert(name.clone(), to_json(row.get_ref(i)?));
+      }
+      results.push(record);
+    }
+    Ok(results)
+  }
+
+  async fn run(&self, statement: Statement) -> Result<RunMeta, BackendError> {
+    let conn = self.conn()?;
+    run_one(&conn, &statement)
+  }
+
+  async fn batch(&self, statements: Vec<Statement>) -> Result<Vec<RunMeta>, BackendError> {
+    let mut conn = self.conn()?;
+    let tx = conn.transaction()?;
+    let results = statements.iter()
+      .map(|s| run_one(&tx, s))
+      .collect::<Result<Vec<_>, _>>()?;
+    tx.commit()?;
+    Ok(results)
+  }
+}
@@ -0,0 +1,18 @@
+use twine_spool_service::server::{init_logger, serve, ServerConfig};
+
+// Queries share one sqlite connection, so one thread is enough
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+  init_logger();
+  let config = match ServerConfig::load() {
+    Ok(config) => config,
+    Err(e) => {
+      log::error!("{}", e);
+      std::process::exit(1);
+    }
+  };
+  if let Err(e) = serve(config).await {
+    log::error!("{}", e);
+    std::process::exit(1);
+  }
+}
@@ -0,0 +1,136 @@
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::PathBuf;
+#[cfg(test)]
+use std::{collections::HashMap, sync::Mutex};
+use async_trait::async_trait;
+#[cfg(target_arch = "wasm32")]
+use worker::{send::{SendFuture, SendWrapper}, Bucket};
+
+#[derive(Debug, thiserror::Error)]
+pub enum BlobError {
+  #[error("Blob storage error: {0}")]
+  Storage(String),
+  #[error("Io error: {0}")]
+  Io(#[from] std::io::Error),
+}
+
+impl From<worker::Error> for BlobError {
+  fn from(e: worker::Error) -> Self {
+    BlobError::Storage(e.to_string())
+  }
+}
+
+/// Storage for block data too large to keep in the database
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+pub trait BlobStore: Send + Sync {
+  async fn put(&self, key: &str, data: &[u8]) -> Result<(), BlobError>;
+  async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, BlobError>;
+  async fn delete(&self, key: &str) -> Result<(), BlobError>;
+}
+
+/// Blobs in a Cloudflare R2 bucket
+#[cfg(target_arch = "wasm32")]
+pub struct R2BlobStore(SendWrapper<Bucket>);
+
+#[cfg(target_arch = "wasm32")]
+impl R2BlobStore {
+  pub fn new(bucket: Bucket) -> Self {
+    Self(SendWrapper::new(bucket))
+  }
+}
+
+#[cfg(target_arch = "wasm32")]
+#[async_trait(?Send)]
+impl BlobStore for R2BlobStore {
+  async fn put(&self, key: &str, data: &[u8]) -> Result<(), BlobError> {
+    SendFuture::new(async move {
+      self.0.put(key, data.to_vec()).execute().await?;
+      Ok(())
+    }).await
+  }
+
+  async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, BlobError> {
+    SendFuture::new(async move {
+      let object = match self.0.get(key).execute().await? {
+        Some(object) => object,
+        None => return Ok(None),
+      };
+      let body = object.body().ok_or(BlobError::Storage(format!("Blob {} has no body", key)))?;
+      Ok(Some(body.bytes().await?))
+    }).await
+  }
+
+  async fn delete(&self, key: &str) -> Result<(), BlobError> {
+    SendFuture::new(async move {
+      self.0.delete(key).await?;
+      Ok(())
+    }).await
+  }
+}
+
+/// Blobs held in memory, for tests
+#[cfg(test)]
+#[derive(Default)]
+pub struct MemoryBlobStore(Mutex<HashMap<String, Vec<u8>>>);
+
+#[cfg(test)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl BlobStore for MemoryBlobStore {
+  async fn put(&self, key: &str, data: &[u8]) -> Result<(), BlobError> {
+    self.0.lock().unwrap().insert(key.to_string(), data.to_vec());
+    Ok(())
+  }
+
+  async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, BlobError> {
+    Ok(self.0.lock().unwrap().get(key).cloned())
+  }
+
+  async fn delete(&self, key: &str) -> Result<(), BlobError> {
+    self.0.lock().unwrap().remove(key);
+    Ok(())
+  }
+}
+
+/// Blobs as files in a local directory, one per key
+#[cfg(not(target_arch = "wasm32"))]
+pub struct FsBlobStore(PathBuf);
+
+#[cfg(not(target_arch = "wasm32"))]
+impl FsBlobStore {
+  pub fn open<P: Into<PathBuf>>(dir: P) -> Result<Self, BlobError> {
+    let dir = dir.into();
+    std::fs::create_dir_all(&dir)?;
+    Ok(Self(dir))
+  }
+
+  fn path(&self, key: &str) -> PathBuf {
+    // keys are cids, so they are safe to use as file names
+    self.0.join(key)
+  }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[async_trait]
+impl BlobStore for FsBlobStore {
+  async fn put(&self, key: &str, data: &[u8]) -> Result<(), BlobError> {
+    std::fs::write(self.path(key), data)?;
+    Ok(())
+  }
+
+  async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, BlobError> {
+    match std::fs::read(self.path(key)) {
+      Ok(data) => Ok(Some(data)),
+      Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+      Err(e) => Err(e.into()),
+    }
+  }
+
+  async fn delete(&self, key: &str) -> Result<(), BlobError> {
+    match std::fs::remove_file(self.path(key)) {
+      Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+      _ => Ok(()),
+    }
+  }
+}
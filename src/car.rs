@@ -1,28 +1,34 @@
-use futures::AsyncRead;
+use futures::io::AsyncRead;
+use futures::stream::{unfold, Stream};
 use fvm_ipld_car::CarReader;
-use crate::ApiError;
+use twine_protocol::twine_lib::{errors::VerificationError, twine::{AnyTwine, TwineBlock}, Cid};
+use crate::errors::ApiError;
 
-use super::*;
+/// A block's cid, and the twine it holds if it is one
+pub type TwineBlockResult = (String, std::result::Result<AnyTwine, VerificationError>);
 
-pub async fn car_to_single_twine<R: AsyncRead + Send + Unpin, T: TwineBlock>(car: R) -> std::result::Result<T, ApiError> {
-  let mut reader = CarReader::new_unchecked(car).await.map_err(|e| ApiError::BadRequestData(e.to_string()))?;
-  let block = reader.next_block().await
-    .map_err(|e| ApiError::BadRequestData(e.to_string()))?
-    .ok_or(ApiError::BadRequestData("No blocks in car".to_string()))?;
-  let cid = Cid::try_from(block.cid.to_bytes()).unwrap();
-  let twine = T::from_block(cid, block.data)?;
-  Ok(twine)
+pub async fn car_reader<R: AsyncRead + Send + Unpin>(car: R) -> std::result::Result<CarReader<R>, ApiError> {
+  CarReader::new_unchecked(car).await.map_err(|e| ApiError::BadRequestData(e.to_string()))
 }
 
-pub async fn car_to_twines<R: AsyncRead + Send + Unpin, T: TwineBlock>(car: R) -> std::result::Result<Vec<T>, ApiError> {
-  let mut reader = CarReader::new_unchecked(car).await.map_err(|e| ApiError::BadRequestData(e.to_string()))?;
-  let mut twines = vec![];
-  while let Some(block) = reader.next_block().await
-    .map_err(|e| ApiError::BadRequestData(e.to_string()))?
-  {
-    let cid = Cid::try_from(block.cid.to_bytes()).unwrap();
-    let twine = T::from_block(cid, block.data)?;
-    twines.push(twine);
-  }
-  Ok(twines)
-}
\ No newline at end of file
+/// The blocks of a CAR as twines, with the cid of each block, read one at a
+/// time as the stream is polled.
+///
+/// Blocks that aren't valid twines come through as a `VerificationError`
+/// and reading carries on. An `ApiError` means the CAR can't be read any
+/// further, and is the last item.
+pub fn car_twines<R: AsyncRead + Send + Unpin>(reader: CarReader<R>) -> impl Stream<Item = std::result::Result<TwineBlockResult, ApiError>> {
+  unfold(Some(reader), |reader| async move {
+    let mut reader = reader?;
+    match reader.next_block().await {
+      Ok(Some(block)) => {
+        let twine = Cid::try_from(block.cid.to_bytes())
+          .map_err(|e| VerificationError::General(e.to_string()))
+          .and_then(|cid| AnyTwine::from_block(cid, block.data));
+        Some((Ok((block.cid.to_string(), twine)), Some(reader)))
+      },
+      Ok(None) => None,
+      Err(e) => Some((Err(ApiError::BadRequestData(e.to_string())), None)),
+    }
+  })
+}
@@ -1,28 +1,41 @@
 use twine_protocol::{prelude::*, twine_lib::ipld_core::codec::Codec};
-use worker::{query, D1Database};
 use twine_protocol::twine_lib::serde_ipld_dagjson::codec::DagJsonCodec;
 use async_trait::async_trait;
 use futures::stream::{unfold, Stream};
 use futures::stream::{StreamExt, TryStreamExt};
 use twine_protocol::twine_lib::as_cid::AsCid;
-use twine_protocol::twine_lib::twine::{AnyTwine, TwineBlock};
-use std::pin::Pin;
+use twine_protocol::twine_lib::twine::{AnyTwine, Twine, TwineBlock};
 use std::sync::Arc;
 use twine_protocol::twine_lib::errors::{ResolutionError, StoreError};
 use twine_protocol::twine_lib::{twine::{Strand, Tixel}, Cid};
-use twine_protocol::twine_lib::resolver::{unchecked_base, Resolver};
+use twine_protocol::twine_lib::resolver::{unchecked_base::{self, TwineStream}, MaybeSend, Resolver};
 use twine_protocol::twine_lib::store::Store;
 use twine_protocol::twine_lib::resolver::AbsoluteRange;
+use crate::errors::WriteError;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use std::collections::{HashMap, HashSet};
+use twine_protocol::twine_lib::ipld_core::ipld::Ipld;
+use crate::backend::{sql, BackendError, Row, SqlBackend, Statement};
+use crate::blob_store::BlobStore;
 
-const BATCH_SIZE : u64 = 1000;
+pub const BATCH_SIZE : u64 = 1000;
+pub const WRITE_BATCH_SIZE : usize = 100;
+pub const STRAND_PAGE_SIZE : u64 = 100;
+/// Payload field holding a tixel's time, unless its strand details
+/// name another one under `timestamp_field`
+pub const DEFAULT_TIMESTAMP_FIELD : &str = "timestamp";
+/// Size in bytes above which tixel data goes to the blob store, if there is one
+pub const DEFAULT_BLOB_THRESHOLD : usize = 256 * 1024;
+/// How long a tixel waits in staging for the tixels before it
+pub const PENDING_TTL_HOURS : i64 = 24 * 7;
+/// Most tixels a strand may have waiting in staging
+pub const MAX_PENDING_PER_STRAND : i64 = 1000;
 
-fn to_resolution_error(err: worker::Error) -> ResolutionError {
-  match err {
-    _ => ResolutionError::Fetch(err.to_string())
-  }
+fn to_resolution_error(err: BackendError) -> ResolutionError {
+  ResolutionError::Fetch(err.to_string())
 }
 
-fn to_storage_error(err: worker::Error) -> StoreError {
+fn to_storage_error(err: BackendError) -> StoreError {
   StoreError::Saving(err.to_string())
 }
 
@@ -32,6 +45,9 @@ struct BlockRecord {
   cid: Vec<u8>,
   #[serde(with = "serde_bytes")]
   data: Vec<u8>,
+  /// Set when the data is in the blob store rather than the database
+  #[serde(default)]
+  blob_key: Option<String>,
 }
 
 impl BlockRecord {
@@ -46,143 +62,621 @@ impl BlockRecord {
   }
 }
 
+#[derive(Debug, Clone, serde::Deserialize)]
+struct StrandRecord {
+  id: i64,
+  #[serde(with = "serde_bytes")]
+  cid: Vec<u8>,
+  #[serde(with = "serde_bytes")]
+  data: Vec<u8>,
+  #[serde(flatten)]
+  stats: StrandStatsRecord,
+}
+
+impl StrandRecord {
+  pub fn into_strand(self) -> Result<(Strand, Option<StrandStats>), VerificationError> {
+    let stats = self.stats.into_stats()?;
+    let strand = BlockRecord { cid: self.cid, data: self.data, blob_key: None }.into_strand()?;
+    Ok((strand, stats))
+  }
+}
+
+/// Columns of `StrandStats`, all optional as they come from a left join
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+struct StrandStatsRecord {
+  latest_idx: Option<u64>,
+  latest_cid: Option<Vec<u8>>,
+  tixel_count: Option<u64>,
+  total_bytes: Option<u64>,
+  first_write_at: Option<NaiveDateTime>,
+  last_write_at: Option<NaiveDateTime>,
+}
+
+impl StrandStatsRecord {
+  fn into_stats(self) -> Result<Option<StrandStats>, VerificationError> {
+    let (latest_index, latest_cid) = match (self.latest_idx, self.latest_cid) {
+      (Some(idx), Some(cid)) => (idx, cid),
+      _ => return Ok(None),
+    };
+    let latest_cid = Cid::try_from(latest_cid).map_err(|e| VerificationError::General(e.to_string()))?;
+    Ok(Some(StrandStats {
+      latest_index,
+      latest_cid: latest_cid.to_string(),
+      tixel_count: self.tixel_count.unwrap_or_default(),
+      total_bytes: self.total_bytes.unwrap_or_default(),
+      first_write_at: self.first_write_at,
+      last_write_at: self.last_write_at,
+    }))
+  }
+}
+
+/// Head and size of a strand with at least one tixel
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct StrandStats {
+  pub latest_index: u64,
+  pub latest_cid: String,
+  pub tixel_count: u64,
+  pub total_bytes: u64,
+  /// Unknown for strands written before stats were kept
+  pub first_write_at: Option<NaiveDateTime>,
+  pub last_write_at: Option<NaiveDateTime>,
+}
+
+/// Columns selected alongside a strand to fill a [`StrandStatsRecord`]
+const STATS_COLUMNS: &str = "StrandStats.latest_idx, StrandStats.latest_cid, StrandStats.tixel_count,
+  StrandStats.total_bytes, StrandStats.first_write_at, StrandStats.last_write_at";
+
+#[derive(Debug, Clone, serde::Deserialize)]
+struct StrandStatusRecord {
+  writable: u8,
+  frozen_reason: Option<String>,
+  frozen_at: Option<NaiveDateTime>,
+  equivocated: u8,
+  write_rate_limit: Option<u32>,
+}
+
+/// Whether a strand accepts new tixels
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct StrandStatus {
+  pub cid: String,
+  pub writable: bool,
+  pub frozen_reason: Option<String>,
+  pub frozen_at: Option<NaiveDateTime>,
+  /// Set once the author has signed two different tixels for one index
+  pub equivocated: bool,
+  /// Writes per minute allowed without an api key, if not the default
+  pub write_rate_limit: Option<u32>,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+struct SignedWriteRecord {
+  write_rate_limit: Option<u32>,
+}
+
+/// Filters for [`D1Store::search_strands`]
+#[derive(Debug, Clone, Default)]
+pub struct StrandSearch {
+  /// Spec string, or a prefix of it ending at a `/`
+  pub spec: Option<String>,
+  /// Pairs of dotted paths into the strand details and the value to match
+  pub details: Vec<(String, String)>,
+}
+
+/// What came of saving one twine
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+  Saved,
+  /// Staged until the tixels before it arrive
+  Staged,
+  /// Already stored
+  Duplicate,
+  /// Not attempted, after an earlier tixel of its strand was refused
+  Skipped,
+  /// Not a valid twine
+  Invalid,
+  /// Refused by the store, with the code of the [`WriteError`]
+  Refused(&'static str),
+}
+
+impl Outcome {
+  pub fn code(&self) -> &'static str {
+    match self {
+      Outcome::Saved => "saved",
+      Outcome::Staged => "staged",
+      Outcome::Duplicate => "duplicate",
+      Outcome::Skipped => "skipped",
+      Outcome::Invalid => "invalid",
+      Outcome::Refused(code) => code,
+    }
+  }
+
+  /// Whether sending the twine again could change anything
+  pub fn is_failure(&self) -> bool {
+    !matches!(self, Outcome::Saved | Outcome::Staged | Outcome::Duplicate)
+  }
+}
+
+impl serde::Serialize for Outcome {
+  fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(self.code())
+  }
+}
+
+/// What came of a batch of saves
+#[derive(Debug, Default)]
+pub struct BatchReport {
+  /// Outcome of each twine, in the order they were given
+  pub outcomes: Vec<(Cid, Outcome)>,
+  /// Why the first refused twine, in the order they were given, was refused
+  pub refusal: Option<WriteError>,
+  /// Position of that twine
+  refused_at: Option<usize>,
+}
+
+impl BatchReport {
+  pub fn count(&self, outcome: Outcome) -> usize {
+    self.outcomes.iter().filter(|(_, o)| *o == outcome).count()
+  }
+
+  /// Record the refusal of the twine at position `at`
+  fn refuse(&mut self, at: usize, reason: WriteError) -> Outcome {
+    let outcome = Outcome::Refused(reason.code());
+    if self.refused_at.is_none_or(|first| at < first) {
+      self.refused_at = Some(at);
+      self.refusal = Some(reason);
+    }
+    outcome
+  }
+}
+
+/// Strands with a refused twine, and the index from which their tixels are
+/// skipped, as they would only be refused for the gap
+#[derive(Debug, Default)]
+pub struct RefusedStrands(HashMap<Cid, u64>);
+
+impl RefusedStrands {
+  /// Skip the tixels of the twine's strand from after it, or all of them
+  /// for a strand
+  pub fn refuse(&mut self, twine: &AnyTwine) {
+    let (strand, from) = match twine {
+      AnyTwine::Strand(s) => (s.cid(), 0),
+      AnyTwine::Tixel(t) => (t.strand_cid(), t.index() + 1),
+    };
+    let at = self.0.entry(strand).or_insert(from);
+    *at = (*at).min(from);
+  }
+
+  pub fn skips(&self, twine: &AnyTwine) -> bool {
+    match twine {
+      AnyTwine::Strand(_) => false,
+      AnyTwine::Tixel(t) => self.0.get(&t.strand_cid()).is_some_and(|&from| t.index() >= from),
+    }
+  }
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+struct EquivocationRecord {
+  #[serde(with = "serde_bytes")]
+  cid: Vec<u8>,
+  #[serde(with = "serde_bytes")]
+  data: Vec<u8>,
+  #[serde(with = "serde_bytes")]
+  stored_cid: Vec<u8>,
+  #[serde(with = "serde_bytes")]
+  stored_data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+struct StitchRecord {
+  id: i64,
+  #[serde(with = "serde_bytes")]
+  from_strand: Vec<u8>,
+  from_idx: u64,
+  #[serde(with = "serde_bytes")]
+  from_tixel: Vec<u8>,
+  #[serde(with = "serde_bytes")]
+  to_strand: Vec<u8>,
+  #[serde(with = "serde_bytes")]
+  to_tixel: Vec<u8>,
+}
+
+impl StitchRecord {
+  fn into_ref(self) -> Result<StitchRef, ResolutionError> {
+    let cid = |bytes: Vec<u8>| Cid::try_from(bytes).map_err(|e| ResolutionError::Fetch(e.to_string()));
+    Ok(StitchRef {
+      from_strand: cid(self.from_strand)?.to_string(),
+      from_index: self.from_idx,
+      from_tixel: cid(self.from_tixel)?.to_string(),
+      to_strand: cid(self.to_strand)?.to_string(),
+      to_tixel: cid(self.to_tixel)?.to_string(),
+    })
+  }
+}
+
+/// A cross stitch from a stored tixel to a tixel of another strand
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct StitchRef {
+  pub from_strand: String,
+  pub from_index: u64,
+  pub from_tixel: String,
+  pub to_strand: String,
+  pub to_tixel: String,
+}
+
+/// Query for [`D1Store::stitches_to`] and [`D1Store::stitches_from`],
+/// selecting the columns of a [`StitchRecord`]
+const STITCH_QUERY: &str = "SELECT st.rowid AS id, s.cid AS from_strand, st.from_idx, t.cid AS from_tixel, st.to_strand, st.to_tixel
+  FROM Stitches st
+  JOIN Strands s ON st.from_strand = s.id
+  JOIN Tixels t ON t.strand = st.from_strand AND t.idx = st.from_idx";
+
+#[derive(Debug, Clone, serde::Deserialize)]
+struct RejectionRecord {
+  writable: u8,
+  existing: Option<Vec<u8>>,
+  previous: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+struct TixelHeadRecord {
+  strand: i64,
+  blob_key: Option<String>,
+  idx: u64,
+  writable: u8,
+  latest: u64,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+struct PendingRecord {
+  #[serde(with = "serde_bytes")]
+  cid: Vec<u8>,
+  idx: u64,
+  size: u64,
+  received_at: NaiveDateTime,
+  expires_at: NaiveDateTime,
+  expired: u8,
+}
+
+/// A tixel waiting in staging for the tixels before it
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct PendingTixel {
+  pub cid: String,
+  pub index: u64,
+  pub size: u64,
+  pub received_at: NaiveDateTime,
+  pub expires_at: NaiveDateTime,
+  pub expired: bool,
+}
+
+/// Check a tixel against the stored strand it claims to belong to,
+/// before anything is written.
+///
+/// The tixel must use the strand's hash algorithm, and make a valid
+/// [`Twine`] with it, which checks its signature against the strand key.
+pub(crate) fn verify_tixel(strand: &Strand, tixel: &Tixel) -> Result<(), VerificationError> {
+  let (expected, actual) = (strand.cid().hash().code(), tixel.cid().hash().code());
+  if expected != actual {
+    return Err(VerificationError::General(format!(
+      "Tixel hash algorithm {:#x} does not match strand hash algorithm {:#x}",
+      actual, expected
+    )));
+  }
+  Twine::try_new(strand.clone(), tixel.clone())?;
+  Ok(())
+}
+
+/// Time in a tixel payload at a dotted field path, in milliseconds since the epoch.
+///
+/// The field may be an RFC 3339 string or a number of seconds.
+fn tixel_timestamp(tixel: &Tixel, field: &str) -> Option<i64> {
+  let payload = tixel.extract_payload::<Ipld>().ok()?;
+  let value = field.split('.').try_fold(&payload, |value, key| match value {
+    Ipld::Map(map) => map.get(key),
+    _ => None,
+  })?;
+  match value {
+    Ipld::String(s) => DateTime::parse_from_rfc3339(s).ok().map(|t| t.timestamp_millis()),
+    Ipld::Integer(secs) => i64::try_from(*secs).ok()?.checked_mul(1000),
+    Ipld::Float(secs) => Some((secs * 1000.0) as i64),
+    _ => None,
+  }
+}
+
+/// The `timestamp_field` named in a strand's details, if any
+fn strand_timestamp_field(strand: &Strand) -> Option<String> {
+  let details = DagJsonCodec::encode_to_vec(strand.details()).ok()?;
+  let details: serde_json::Value = serde_json::from_slice(&details).ok()?;
+  details.get("timestamp_field")?.as_str().map(String::from)
+}
+
 #[derive(Clone)]
 pub struct D1Store {
-  pub db: Arc<D1Database>,
+  pub db: Arc<dyn SqlBackend>,
+  /// Payload field read for tixel times when the strand doesn't name one
+  pub timestamp_field: String,
+  /// Where tixel data larger than `blob_threshold` is kept
+  pub blobs: Option<Arc<dyn BlobStore>>,
+  pub blob_threshold: usize,
 }
 
 impl D1Store {
-  pub fn new(db: D1Database) -> Self {
-    Self { db: Arc::new(db) }
-  }
-
-  async fn all_strands(&self) -> Result<Pin<Box<dyn Stream<Item = Result<Strand, ResolutionError>> + '_>>, ResolutionError> {
-    async fn next_page(db: &D1Database, offset: i64) -> Result<Vec<Result<Strand, ResolutionError>>, ResolutionError> {
-      let query_str = "SELECT cid, data FROM Strands LIMIT 100 OFFSET $1";
-      let query = query!(db, query_str, offset).map_err(to_resolution_error)?;
-      let result = query.all().await.map_err(to_resolution_error)?;
-      let results = result.results::<BlockRecord>().map_err(to_resolution_error)?;
-      let strands = results.into_iter().map(|block| {
-        Ok(block.into_strand()?)
-      });
-      Ok(strands.collect())
+  #[cfg(test)]
+  pub fn with_backend<B: SqlBackend + 'static>(backend: B) -> Self {
+    Self::with_shared_backend(Arc::new(backend))
+  }
+
+  pub fn with_shared_backend(db: Arc<dyn SqlBackend>) -> Self {
+    Self {
+      db,
+      timestamp_field: DEFAULT_TIMESTAMP_FIELD.to_string(),
+      blobs: None,
+      blob_threshold: DEFAULT_BLOB_THRESHOLD,
     }
+  }
+
+  pub fn with_timestamp_field<S: Into<String>>(mut self, field: S) -> Self {
+    self.timestamp_field = field.into();
+    self
+  }
+
+  pub fn with_blob_store(mut self, blobs: Arc<dyn BlobStore>, threshold: usize) -> Self {
+    self.blobs = Some(blobs);
+    self.blob_threshold = threshold;
+    self
+  }
+
+  /// Decode a stored tixel, fetching its data from the blob store if it was offloaded
+  async fn load_tixel(&self, mut block: BlockRecord) -> Result<Tixel, ResolutionError> {
+    if let Some(key) = block.blob_key.take() {
+      let blobs = self.blobs.as_ref()
+        .ok_or(ResolutionError::Fetch(format!("No blob store to read blob {} from", key)))?;
+      block.data = blobs.get(&key).await
+        .map_err(|e| ResolutionError::Fetch(e.to_string()))?
+        .ok_or(ResolutionError::Fetch(format!("Blob {} is missing", key)))?;
+    }
+    Ok(block.into_tixel()?)
+  }
+
+  /// Put a large tixel's data in the blob store, returning its key
+  async fn offload(&self, tixel: &Tixel) -> Result<Option<String>, StoreError> {
+    let blobs = match &self.blobs {
+      Some(blobs) if tixel.bytes().len() > self.blob_threshold => blobs,
+      _ => return Ok(None),
+    };
+    // keyed by cid, so saving the same tixel again reuses the blob
+    let key = tixel.cid().to_string();
+    blobs.put(&key, &tixel.bytes()).await.map_err(|e| StoreError::Saving(e.to_string()))?;
+    Ok(Some(key))
+  }
 
-    let stream = unfold(0, move |offset| {
+  /// Remove blobs that no stored tixel refers to any more
+  async fn delete_blobs(&self, keys: &[String]) {
+    let blobs = match &self.blobs {
+      Some(blobs) => blobs,
+      None => return,
+    };
+    for key in keys {
+      if let Err(e) = blobs.delete(key).await {
+        log::warn!("Could not delete blob {}: {}", key, e);
+      }
+    }
+  }
+
+  /// Fetch up to `limit` strands with ids after the `after` cursor.
+  ///
+  /// Also returns the cursor for the following page, if there may be one.
+  pub async fn strands_page(&self, after: i64, limit: u64) -> Result<(Vec<(Strand, Option<StrandStats>)>, Option<i64>), ResolutionError> {
+    self.search_strands(&StrandSearch::default(), after, limit).await
+  }
+
+  /// Like [`D1Store::strands_page`] but only strands matching the search
+  pub async fn search_strands(&self, search: &StrandSearch, after: i64, limit: u64) -> Result<(Vec<(Strand, Option<StrandStats>)>, Option<i64>), ResolutionError> {
+    let mut conditions = vec!["Strands.id > ?1".to_string()];
+    let mut params: Vec<String> = vec![];
+    if let Some(spec) = &search.spec {
+      params.push(spec.clone());
+      // compared by prefix rather than LIKE, so `_` and `%` in specs match only themselves
+      conditions.push(format!(
+        "(Strands.spec = ?{0} OR substr(Strands.spec, 1, length(?{0}) + 1) = ?{0} || '/')",
+        params.len() + 2
+      ));
+    }
+    for (path, value) in &search.details {
+      params.push(format!("$.{}", path));
+      params.push(value.clone());
+      // json_each matches scalars as well as any item of an array
+      conditions.push(format!(
+        "EXISTS (SELECT 1 FROM json_each(Strands.details, ?{}) j WHERE CAST(j.value AS TEXT) = ?{})",
+        params.len() + 1,
+        params.len() + 2
+      ));
+    }
+    let query_str = format!(
+      "SELECT Strands.id, Strands.cid, Strands.data, {}
+      FROM Strands
+      LEFT JOIN StrandStats ON StrandStats.strand = Strands.id
+      WHERE {}
+      ORDER BY Strands.id ASC
+      LIMIT ?2",
+      STATS_COLUMNS,
+      conditions.join(" AND ")
+    );
+    let query = params.iter().fold(
+      sql!(self.db, &query_str, after, limit as i64),
+      |query, param| query.bind(param)
+    );
+    let records = self.db.all::<StrandRecord>(query).await.map_err(to_resolution_error)?;
+    let next = if records.len() as u64 >= limit {
+      records.last().map(|r| r.id)
+    } else {
+      None
+    };
+    let strands = records.into_iter()
+      .map(|r| r.into_strand())
+      .collect::<Result<Vec<_>, _>>()?;
+    Ok((strands, next))
+  }
+
+  async fn all_strands(&self) -> Result<TwineStream<'_, Strand>, ResolutionError> {
+    let stream = unfold(Some(0), move |after| {
       async move {
-        let strands = match next_page(&self.db, offset).await {
-          Ok(strands) => strands,
-          Err(e) => return Some((Err(e), offset)),
-        };
-        if strands.is_empty() {
-          return None;
+        let after = after?;
+        match self.strands_page(after, STRAND_PAGE_SIZE).await {
+          Ok((strands, _)) if strands.is_empty() => None,
+          Ok((strands, next)) => Some((Ok(strands.into_iter().map(|(s, _)| s)), next)),
+          Err(e) => Some((Err(e), None)),
         }
-        Some((Ok(strands), offset + 100))
       }
     })
-    .map_ok(|v| futures::stream::iter(v.into_iter()))
-    .try_flatten()
-    .boxed_local();
+    .map_ok(|v| futures::stream::iter(v.into_iter().map(Ok)))
+    .try_flatten();
 
-    Ok(stream)
+    Ok(Box::pin(stream))
   }
 
   pub async fn get_strand(&self, cid: &Cid) -> Result<Strand, ResolutionError> {
-    let query = query!(&self.db, "SELECT data FROM Strands WHERE cid = ?1", cid.to_bytes()).map_err(to_resolution_error)?;
-    let result = query.first::<Vec<u8>>(Some("data")).await.map_err(to_resolution_error)?;
+    let query = sql!(self.db, "SELECT data FROM Strands WHERE cid = ?1", cid.to_bytes());
+    let result = self.db.first::<Vec<u8>>(query, Some("data")).await.map_err(to_resolution_error)?;
     let bytes = result.ok_or(ResolutionError::NotFound)?;
     let strand = Strand::from_block(*cid, bytes)?;
     Ok(strand)
   }
 
-  pub async fn has_tixel(&self, cid: &Cid) -> Result<bool, ResolutionError> {
-    let query = query!(
-      &self.db,
-      "SELECT TRUE FROM Tixels WHERE cid = ?1 LIMIT 1",
+  pub async fn is_tombstoned(&self, cid: &Cid) -> Result<bool, ResolutionError> {
+    let query = sql!(
+      self.db,
+      "SELECT TRUE FROM StrandTombstones WHERE cid = ?1 LIMIT 1",
       cid.to_bytes()
-    ).map_err(to_resolution_error)?;
-    let result = query.first::<u8>(Some("TRUE")).await.map_err(to_resolution_error)?;
+    );
+    let result = self.db.first::<u8>(query, Some("TRUE")).await.map_err(to_resolution_error)?;
     Ok(result.is_some())
   }
 
   async fn has_strand_cid(&self, cid: &Cid) -> Result<bool, ResolutionError> {
-    let query = query!(
-      &self.db,
+    let query = sql!(
+      self.db,
       "SELECT TRUE FROM Strands WHERE cid = ?1 LIMIT 1",
       cid.to_bytes()
-    ).map_err(to_resolution_error)?;
-    let result = query.first::<u8>(Some("TRUE")).await.map_err(to_resolution_error)?;
+    );
+    let result = self.db.first::<u8>(query, Some("TRUE")).await.map_err(to_resolution_error)?;
     Ok(result.is_some())
   }
 
-  async fn cid_for_index(&self, strand: &Cid, index: u64) -> Result<Cid, ResolutionError> {
-    let query = "SELECT t.cid FROM Tixels t JOIN Strands s ON t.strand = s.id WHERE s.cid = $1 AND t.idx = $2";
-
-    let query = query!(&self.db, query, strand.to_bytes(), index as i64).map_err(to_resolution_error)?;
-    let result: Option<Vec<u8>> = query.first(Some("cid")).await.map_err(to_resolution_error)?;
-    let bytes = result.ok_or(ResolutionError::NotFound)?;
+  #[cfg(test)]
+  pub async fn get_tixel(&self, cid: &Cid) -> Result<Tixel, ResolutionError> {
+    let query = sql!(self.db, "SELECT cid, data, blob_key FROM Tixels WHERE cid = ?1", cid.to_bytes());
+    let result = self.db.first::<BlockRecord>(query, None).await.map_err(to_resolution_error)?;
+    let block = result.ok_or(ResolutionError::NotFound)?;
+    self.load_tixel(block).await
+  }
 
-    Ok(Cid::try_from(bytes).map_err(|e| ResolutionError::Fetch(e.to_string()))?)
+  async fn has_tixel_in_strand(&self, strand_cid: &Cid, cid: &Cid) -> Result<bool, ResolutionError> {
+    let query = sql!(
+      self.db,
+      "SELECT TRUE
+      FROM Tixels t
+      JOIN Strands s ON t.strand = s.id
+      WHERE s.cid = ?1 AND t.cid = ?2
+      LIMIT 1",
+      strand_cid.to_bytes(),
+      cid.to_bytes()
+    );
+    let result = self.db.first::<u8>(query, Some("TRUE")).await.map_err(to_resolution_error)?;
+    Ok(result.is_some())
   }
 
-  pub async fn get_tixel(&self, cid: &Cid) -> Result<Tixel, ResolutionError> {
-    let query = query!(&self.db, "SELECT data FROM Tixels WHERE cid = ?1", cid.to_bytes()).map_err(to_resolution_error)?;
-    let result = query.first::<Vec<u8>>(Some("data")).await.map_err(to_resolution_error)?;
-    let bytes = result.ok_or(ResolutionError::NotFound)?;
-    Ok(Tixel::from_block(*cid, bytes)?)
+  async fn get_tixel_in_strand(&self, strand_cid: &Cid, cid: &Cid) -> Result<Tixel, ResolutionError> {
+    let query = sql!(
+      self.db,
+      "SELECT t.cid, t.data, t.blob_key
+      FROM Tixels t
+      JOIN Strands s ON t.strand = s.id
+      WHERE s.cid = ?1 AND t.cid = ?2",
+      strand_cid.to_bytes(),
+      cid.to_bytes()
+    );
+    let result = self.db.first::<BlockRecord>(query, None).await.map_err(to_resolution_error)?;
+    let block = result.ok_or(ResolutionError::NotFound)?;
+    self.load_tixel(block).await
   }
 
-  async fn get_tixel_by_index(&self, strand_cid: &Cid, index: u64) -> Result<Tixel, ResolutionError> {
-    let query = query!(
-      &self.db,
-      "SELECT Tixels.cid, Tixels.data
+  pub async fn get_tixel_by_index(&self, strand_cid: &Cid, index: u64) -> Result<Tixel, ResolutionError> {
+    let query = sql!(
+      self.db,
+      "SELECT Tixels.cid, Tixels.data, Tixels.blob_key
       FROM Tixels
       JOIN Strands ON Tixels.strand = Strands.id
       WHERE Strands.cid = ?1
       AND Tixels.idx = ?2;",
       strand_cid.to_bytes(),
       index
-    ).map_err(to_resolution_error)?;
-    let result = query.first::<BlockRecord>(None).await.map_err(to_resolution_error)?;
+    );
+    let result = self.db.first::<BlockRecord>(query, None).await.map_err(to_resolution_error)?;
     let block = result.ok_or(ResolutionError::NotFound)?;
-    Ok(block.into_tixel()?)
+    self.load_tixel(block).await
   }
 
   async fn latest_tixel(&self, strand_cid: &Cid) -> Result<Tixel, ResolutionError> {
-    let query = query!(
-      &self.db,
-      "SELECT Tixels.cid, Tixels.data
-      FROM Tixels
-      JOIN Strands ON Tixels.strand = Strands.id
-      WHERE Strands.cid = ?1
-      ORDER BY Tixels.idx DESC
-      LIMIT 1;",
+    let query = sql!(
+      self.db,
+      "SELECT Tixels.cid, Tixels.data, Tixels.blob_key
+      FROM StrandStats
+      JOIN Strands ON StrandStats.strand = Strands.id
+      JOIN Tixels ON Tixels.cid = StrandStats.latest_cid
+      WHERE Strands.cid = ?1;",
       strand_cid.to_bytes()
-    ).map_err(to_resolution_error)?;
-    let result = query.first::<BlockRecord>(None).await.map_err(to_resolution_error)?;
+    );
+    let result = self.db.first::<BlockRecord>(query, None).await.map_err(to_resolution_error)?;
     let block = result.ok_or(ResolutionError::NotFound)?;
-    Ok(block.into_tixel()?)
+    self.load_tixel(block).await
   }
 
-  async fn save_strand(&self, strand: &Strand) -> Result<(), StoreError> {
-    let query = query!(
-      &self.db,
+  /// Tixels are stored without gaps, so any index up to the latest exists
+  async fn has_index_up_to_latest(&self, strand_cid: &Cid, index: u64) -> Result<bool, ResolutionError> {
+    let query = sql!(
+      self.db,
+      "SELECT TRUE
+      FROM StrandStats
+      JOIN Strands ON StrandStats.strand = Strands.id
+      WHERE Strands.cid = ?1 AND StrandStats.latest_idx >= ?2",
+      strand_cid.to_bytes(),
+      index as i64
+    );
+    let result = self.db.first::<u8>(query, Some("TRUE")).await.map_err(to_resolution_error)?;
+    Ok(result.is_some())
+  }
+
+  pub async fn strand_stats(&self, strand_cid: &Cid) -> Result<Option<StrandStats>, ResolutionError> {
+    let query = sql!(
+      self.db,
+      &format!(
+        "SELECT {} FROM StrandStats JOIN Strands ON StrandStats.strand = Strands.id WHERE Strands.cid = ?1",
+        STATS_COLUMNS
+      ),
+      strand_cid.to_bytes()
+    );
+    let record = self.db.first::<StrandStatsRecord>(query, None).await.map_err(to_resolution_error)?;
+    Ok(record.unwrap_or_default().into_stats()?)
+  }
+
+  fn save_strand_statement(&self, strand: &Strand) -> Statement {
+    sql!(
+      self.db,
       "INSERT OR IGNORE INTO Strands (cid, data, spec, details)
-      VALUES (?1, ?2, ?3, ?4);",
+      SELECT ?1, ?2, ?3, ?4
+      WHERE NOT EXISTS (SELECT 1 FROM StrandTombstones WHERE cid = ?1);",
       strand.cid().to_bytes(),
       strand.bytes(),
       strand.spec_str(),
       String::from_utf8(DagJsonCodec::encode_to_vec(strand.details()).unwrap()).unwrap()
-    ).map_err(to_storage_error)?;
-    query.run().await.map_err(to_storage_error)?;
-    log::info!("New strand saved: {}", strand.cid());
-    Ok(())
+    )
   }
 
-  async fn save_tixel(&self, tixel: &Tixel) -> Result<(), StoreError> {
+  fn save_tixel_statement(&self, tixel: &Tixel, timestamp_field: &str, blob_key: Option<&str>) -> Statement {
+    // only inserts if the previous tixel is already stored
     let query = "
-      INSERT OR IGNORE INTO Tixels (cid, data, strand, idx)
-      SELECT ?1, ?2, s.id, ?4
+      INSERT OR IGNORE INTO Tixels (cid, data, strand, idx, timestamp_ms, blob_key, size)
+      SELECT ?1, ?2, s.id, ?4, ?6, ?7, ?8
       FROM Strands s
       WHERE s.cid = ?3
         AND s.writable = 1
@@ -196,36 +690,934 @@ impl D1Store {
           )
         );
     ";
-    query!(
-      &self.db,
+    sql!(
+      self.db,
       query,
       tixel.cid().to_bytes(),
-      tixel.bytes(),
+      // offloaded data is left out of the database
+      if blob_key.is_some() { vec![] } else { tixel.bytes().to_vec() },
       tixel.strand_cid().to_bytes(),
       tixel.index() as i64,
-      tixel.previous().map(|s| s.tixel.to_bytes())
+      tixel.previous().map(|s| s.tixel.to_bytes()),
+      tixel_timestamp(tixel, timestamp_field),
+      blob_key,
+      tixel.bytes().len() as i64
+    )
+  }
+
+  /// Payload field holding the times of a strand's tixels
+  fn timestamp_field_of(&self, strand: &Strand) -> String {
+    strand_timestamp_field(strand).unwrap_or_else(|| self.timestamp_field.clone())
+  }
+
+  /// The strands of every tixel in a batch, including strands saved in the
+  /// same batch. Strands that aren't stored are left out.
+  async fn batch_strands(&self, twines: &[AnyTwine]) -> Result<HashMap<Cid, Strand>, ResolutionError> {
+    let mut strands: HashMap<Cid, Strand> = twines.iter()
+      .filter_map(|twine| match twine {
+        AnyTwine::Strand(s) => Some((s.cid(), s.clone())),
+        AnyTwine::Tixel(_) => None,
+      })
+      .collect();
+    let mut missing = HashSet::new();
+    for twine in twines {
+      if let AnyTwine::Tixel(t) = twine {
+        let cid = t.strand_cid();
+        if strands.contains_key(&cid) || missing.contains(&cid) {
+          continue;
+        }
+        match self.get_strand(&cid).await {
+          Ok(strand) => { strands.insert(cid, strand); },
+          Err(ResolutionError::NotFound) => { missing.insert(cid); },
+          Err(e) => return Err(e),
+        }
+      }
+    }
+    Ok(strands)
+  }
+
+  /// Moves the strand head forward to a tixel saved earlier in the same batch.
+  ///
+  /// Does nothing if the tixel wasn't saved or the head is already past it.
+  fn update_stats_statement(&self, tixel: &Tixel) -> Statement {
+    sql!(
+      self.db,
+      "INSERT INTO StrandStats (strand, latest_idx, latest_cid, tixel_count, total_bytes, first_write_at, last_write_at)
+      SELECT t.strand, t.idx, t.cid, 1, COALESCE(t.size, LENGTH(t.data)), ?2, ?2
+      FROM Tixels t
+      WHERE t.cid = ?1
+      ON CONFLICT (strand) DO UPDATE SET
+        latest_idx = excluded.latest_idx,
+        latest_cid = excluded.latest_cid,
+        tixel_count = StrandStats.tixel_count + 1,
+        total_bytes = StrandStats.total_bytes + excluded.total_bytes,
+        last_write_at = excluded.last_write_at
+      WHERE excluded.latest_idx > StrandStats.latest_idx;",
+      tixel.cid().to_bytes(),
+      Utc::now().naive_utc()
     )
-    .map_err(to_storage_error)?
-    .run()
-    .await
-    .map_err(to_storage_error)?;
-    log::debug!("Saved Tixel {}:{}", tixel.strand_cid(), tixel.cid());
+  }
+
+  /// Records the cross stitches of a tixel saved earlier in the same batch
+  fn save_stitch_statements(&self, tixel: &Tixel) -> Vec<Statement> {
+    tixel.cross_stitches().stitches().into_iter()
+      .map(|stitch| sql!(
+        self.db,
+        "INSERT OR IGNORE INTO Stitches (from_strand, from_idx, to_strand, to_tixel)
+        SELECT t.strand, t.idx, ?2, ?3
+        FROM Tixels t
+        WHERE t.cid = ?1;",
+        tixel.cid().to_bytes(),
+        stitch.strand.to_bytes(),
+        stitch.tixel.to_bytes()
+      ))
+      .collect()
+  }
+
+  /// Everything written for a tixel, starting with the tixel itself
+  fn save_tixel_statements(&self, tixel: &Tixel, timestamp_field: &str, blob_key: Option<&str>) -> Vec<Statement> {
+    let mut statements = vec![
+      self.save_tixel_statement(tixel, timestamp_field, blob_key),
+      self.update_stats_statement(tixel),
+    ];
+    statements.extend(self.save_stitch_statements(tixel));
+    statements
+  }
+
+  async fn save_strand(&self, strand: &Strand) -> Result<(), WriteError> {
+    let result = self.db.run(self.save_strand_statement(strand)).await.map_err(to_storage_error)?;
+    if result.changes == 0 && self.is_tombstoned(&strand.cid()).await.map_err(StoreError::from)? {
+      return Err(WriteError::Removed);
+    }
+    log::info!("New strand saved: {}", strand.cid());
     Ok(())
   }
 
-  async fn remove_strand(&self, _cid: &Cid) -> Result<(), StoreError> {
-    unimplemented!()
+  /// Save a tixel, reporting why it was refused if nothing was inserted
+  pub async fn save_tixel(&self, tixel: &Tixel) -> Result<Outcome, WriteError> {
+    let strand = match self.get_strand(&tixel.strand_cid()).await {
+      Ok(strand) => strand,
+      Err(ResolutionError::NotFound) => return Err(WriteError::UnknownStrand),
+      Err(e) => return Err(StoreError::from(e).into()),
+    };
+    verify_tixel(&strand, tixel).map_err(WriteError::InvalidSignature)?;
+    let timestamp_field = self.timestamp_field_of(&strand);
+    let blob_key = self.offload(tixel).await?;
+    let statements = self.save_tixel_statements(tixel, &timestamp_field, blob_key.as_deref());
+    let results = self.db.batch(statements).await.map_err(to_storage_error)?;
+    let outcome = if results[0].changes > 0 {
+      log::debug!("Saved Tixel {}:{}", tixel.strand_cid(), tixel.cid());
+      Outcome::Saved
+    } else {
+      match self.rejection_reason(tixel).await? {
+        WriteError::Gap if self.stage_tixel(tixel, blob_key.as_deref()).await? => Outcome::Staged,
+        reason => {
+          if matches!(reason, WriteError::Fork) {
+            self.record_equivocation(tixel).await?;
+          }
+          // a duplicate is stored under the same blob key
+          if let (Some(key), false) = (blob_key, matches!(reason, WriteError::Duplicate)) {
+            self.delete_blobs(&[key]).await;
+          }
+          return Err(reason);
+        },
+      }
+    };
+    self.promote_pending(&strand).await?;
+    Ok(outcome)
   }
 
-  async fn remove_tixel_if_latest(&self, _cid: &Cid) -> Result<(), StoreError> {
-    unimplemented!()
+  /// Hold a tixel that doesn't connect to its strand yet, until the
+  /// tixels before it arrive or [`PENDING_TTL_HOURS`] pass. Expired tixels
+  /// of the strand are dropped first.
+  ///
+  /// Returns false if the strand already has [`MAX_PENDING_PER_STRAND`]
+  /// tixels waiting, and the tixel was not staged.
+  async fn stage_tixel(&self, tixel: &Tixel, blob_key: Option<&str>) -> Result<bool, StoreError> {
+    self.purge_pending(&tixel.strand_cid(), true).await?;
+    let query = sql!(
+      self.db,
+      "INSERT OR IGNORE INTO PendingTixels (cid, strand, idx, data, blob_key, size, expires_at)
+      SELECT ?1, s.id, ?3, ?4, ?5, ?6, datetime('now', ?7)
+      FROM Strands s
+      WHERE s.cid = ?2
+        AND (SELECT COUNT(*) FROM PendingTixels p WHERE p.strand = s.id) < ?8;",
+      tixel.cid().to_bytes(),
+      tixel.strand_cid().to_bytes(),
+      tixel.index() as i64,
+      if blob_key.is_some() { vec![] } else { tixel.bytes().to_vec() },
+      blob_key,
+      tixel.bytes().len() as i64,
+      format!("+{} hours", PENDING_TTL_HOURS),
+      MAX_PENDING_PER_STRAND
+    );
+    if self.db.run(query).await.map_err(to_storage_error)?.changes == 0 {
+      // staged already, or staging is full
+      let query = sql!(self.db, "SELECT TRUE FROM PendingTixels WHERE cid = ?1", tixel.cid().to_bytes());
+      let staged = self.db.first::<u8>(query, Some("TRUE")).await.map_err(to_storage_error)?.is_some();
+      if !staged {
+        log::info!("Staging is full for strand {}, refusing Tixel {}", tixel.strand_cid(), tixel.cid());
+      }
+      return Ok(staged);
+    }
+    log::debug!("Staged Tixel {}:{} at index {}", tixel.strand_cid(), tixel.cid(), tixel.index());
+    Ok(true)
+  }
+
+  /// Move staged tixels of a strand into place, for as long as the next one
+  /// connects. Staged tixels at or behind the head are dropped, and kept as
+  /// equivocations if they conflict with the stored ones.
+  ///
+  /// Returns how many tixels were promoted.
+  pub async fn promote_pending(&self, strand: &Strand) -> Result<usize, StoreError> {
+    let timestamp_field = self.timestamp_field_of(strand);
+    let mut promoted = 0;
+    loop {
+      let query = sql!(
+        self.db,
+        "SELECT p.cid, p.data, p.blob_key
+        FROM PendingTixels p
+        JOIN Strands s ON p.strand = s.id
+        WHERE s.cid = ?1
+          AND p.expires_at > CURRENT_TIMESTAMP
+          AND p.idx <= COALESCE((SELECT latest_idx + 1 FROM StrandStats WHERE strand = s.id), 0)
+        ORDER BY p.idx, p.received_at;",
+        strand.cid().to_bytes()
+      );
+      let candidates = self.db.all::<BlockRecord>(query).await.map_err(to_storage_error)?;
+      let mut advanced = false;
+      for record in candidates {
+        let blob_key = record.blob_key.clone();
+        let tixel = self.load_tixel(record).await?;
+        let mut statements = self.save_tixel_statements(&tixel, &timestamp_field, blob_key.as_deref());
+        statements.push(sql!(self.db, "DELETE FROM PendingTixels WHERE cid = ?1;", tixel.cid().to_bytes()));
+        let results = self.db.batch(statements).await.map_err(to_storage_error)?;
+        if results[0].changes > 0 {
+          log::debug!("Promoted staged Tixel {}:{}", tixel.strand_cid(), tixel.cid());
+          promoted += 1;
+          advanced = true;
+          continue;
+        }
+        let reason = self.rejection_reason(&tixel).await?;
+        if matches!(reason, WriteError::Fork) {
+          self.record_equivocation(&tixel).await?;
+        }
+        log::info!("Dropped staged Tixel {}:{}: {}", tixel.strand_cid(), tixel.cid(), reason);
+        if let (Some(key), false) = (blob_key, matches!(reason, WriteError::Duplicate)) {
+          self.delete_blobs(&[key]).await;
+        }
+      }
+      if !advanced {
+        return Ok(promoted);
+      }
+    }
+  }
+
+  /// Tixels of a strand waiting in staging, by index
+  pub async fn pending_tixels(&self, strand_cid: &Cid) -> Result<Vec<PendingTixel>, ResolutionError> {
+    let query = sql!(
+      self.db,
+      "SELECT p.cid, p.idx, p.size,
+        strftime('%Y-%m-%dT%H:%M:%S', p.received_at) AS received_at,
+        strftime('%Y-%m-%dT%H:%M:%S', p.expires_at) AS expires_at,
+        p.expires_at <= CURRENT_TIMESTAMP AS expired
+      FROM PendingTixels p
+      JOIN Strands s ON p.strand = s.id
+      WHERE s.cid = ?1
+      ORDER BY p.idx, p.received_at;",
+      strand_cid.to_bytes()
+    );
+    let records = self.db.all::<PendingRecord>(query).await.map_err(to_resolution_error)?;
+    let mut pending = Vec::with_capacity(records.len());
+    for r in records {
+      let cid = Cid::try_from(r.cid).map_err(|e| VerificationError::General(e.to_string()))?;
+      pending.push(PendingTixel {
+        cid: cid.to_string(),
+        index: r.idx,
+        size: r.size,
+        received_at: r.received_at,
+        expires_at: r.expires_at,
+        expired: r.expired != 0,
+      });
+    }
+    Ok(pending)
+  }
+
+  /// Remove staged tixels of a strand, or only those that have expired.
+  ///
+  /// Returns how many were removed.
+  pub async fn purge_pending(&self, strand_cid: &Cid, expired_only: bool) -> Result<usize, StoreError> {
+    let condition = if expired_only { "AND expires_at <= CURRENT_TIMESTAMP" } else { "" };
+    let query = sql!(
+      self.db,
+      &format!(
+        "SELECT blob_key FROM PendingTixels
+        WHERE strand = (SELECT id FROM Strands WHERE cid = ?1) AND blob_key IS NOT NULL {}
+          AND blob_key NOT IN (SELECT blob_key FROM Tixels WHERE blob_key IS NOT NULL);",
+        condition
+      ),
+      strand_cid.to_bytes()
+    );
+    let blob_keys: Vec<String> = self.db.all::<Row>(query).await
+      .map_err(to_storage_error)?
+      .into_iter()
+      .filter_map(|row| row.get("blob_key")?.as_str().map(String::from))
+      .collect();
+    let query = sql!(
+      self.db,
+      &format!(
+        "DELETE FROM PendingTixels WHERE strand = (SELECT id FROM Strands WHERE cid = ?1) {};",
+        condition
+      ),
+      strand_cid.to_bytes()
+    );
+    let meta = self.db.run(query).await.map_err(to_storage_error)?;
+    self.delete_blobs(&blob_keys).await;
+    if meta.changes > 0 {
+      log::info!("Purged {} staged tixels of strand {}", meta.changes, strand_cid);
+    }
+    Ok(meta.changes)
+  }
+
+  /// Work out why [`Self::save_tixel_statement`] left a tixel out
+  async fn rejection_reason(&self, tixel: &Tixel) -> Result<WriteError, StoreError> {
+    let query = sql!(
+      self.db,
+      "SELECT s.writable,
+        (SELECT t.cid FROM Tixels t WHERE t.strand = s.id AND t.idx = ?2) AS existing,
+        (SELECT t.cid FROM Tixels t WHERE t.strand = s.id AND t.idx = ?2 - 1) AS previous
+      FROM Strands s
+      WHERE s.cid = ?1;",
+      tixel.strand_cid().to_bytes(),
+      tixel.index() as i64
+    );
+    let record = self.db.first::<RejectionRecord>(query, None).await.map_err(to_storage_error)?;
+    let record = match record {
+      Some(record) => record,
+      None => return Ok(WriteError::UnknownStrand),
+    };
+    let expected_previous = tixel.previous().map(|p| p.tixel.to_bytes());
+    let reason = match record.existing {
+      Some(existing) if existing == tixel.cid().to_bytes() => WriteError::Duplicate,
+      Some(_) => WriteError::Fork,
+      None if record.writable == 0 => WriteError::NotWritable,
+      None if tixel.index() == 0 => WriteError::Store(StoreError::Saving("Tixel was not saved".into())),
+      None => match record.previous {
+        None => WriteError::Gap,
+        Some(previous) if expected_previous.as_ref() != Some(&previous) => WriteError::Fork,
+        Some(_) => WriteError::Store(StoreError::Saving("Tixel was not saved".into())),
+      },
+    };
+    Ok(reason)
+  }
+
+  /// Keep a verified tixel that conflicts with the stored one at its index,
+  /// along with the stored one so the evidence outlives it, and flag its
+  /// strand. Forks from a stored previous tixel leave nothing to keep.
+  async fn record_equivocation(&self, tixel: &Tixel) -> Result<(), StoreError> {
+    let strand_cid = tixel.strand_cid().to_bytes();
+    let query = sql!(
+      self.db,
+      "SELECT t.cid, t.data, t.blob_key
+      FROM Tixels t
+      JOIN Strands s ON t.strand = s.id
+      WHERE s.cid = ?1 AND t.idx = ?2;",
+      strand_cid,
+      tixel.index() as i64
+    );
+    let stored = match self.db.first::<BlockRecord>(query, None).await.map_err(to_storage_error)? {
+      Some(record) if record.cid != tixel.cid().to_bytes() => self.load_tixel(record).await?,
+      _ => return Ok(()),
+    };
+    let statements = vec![
+      sql!(
+        self.db,
+        "INSERT OR IGNORE INTO Equivocations (cid, strand, idx, data, stored_cid, stored_data)
+        SELECT ?1, s.id, ?3, ?4, ?5, ?6 FROM Strands s WHERE s.cid = ?2;",
+        tixel.cid().to_bytes(),
+        strand_cid,
+        tixel.index() as i64,
+        tixel.bytes(),
+        stored.cid().to_bytes(),
+        stored.bytes()
+      ),
+      sql!(self.db, "UPDATE Strands SET equivocated = 1 WHERE cid = ?1;", strand_cid),
+    ];
+    self.db.batch(statements).await.map_err(to_storage_error)?;
+    log::warn!("Equivocation detected on strand {} at index {}", tixel.strand_cid(), tixel.index());
+    Ok(())
+  }
+
+  /// Pairs of (stored, conflicting) tixels recorded for a strand, by index
+  pub async fn equivocations(&self, strand_cid: &Cid) -> Result<Vec<(Tixel, Tixel)>, ResolutionError> {
+    let query = sql!(
+      self.db,
+      "SELECT e.cid, e.data, e.stored_cid, e.stored_data
+      FROM Equivocations e
+      JOIN Strands s ON e.strand = s.id
+      WHERE s.cid = ?1
+      ORDER BY e.idx;",
+      strand_cid.to_bytes()
+    );
+    let records = self.db.all::<EquivocationRecord>(query).await.map_err(to_resolution_error)?;
+    let mut pairs = Vec::with_capacity(records.len());
+    for r in records {
+      let stored = BlockRecord { cid: r.stored_cid, data: r.stored_data, blob_key: None }.into_tixel()?;
+      let conflicting = BlockRecord { cid: r.cid, data: r.data, blob_key: None }.into_tixel()?;
+      pairs.push((stored, conflicting));
+    }
+    Ok(pairs)
+  }
+
+  /// The last tixel of a strand with a time at or before `at`
+  pub async fn tixel_at_time(&self, strand_cid: &Cid, at: DateTime<Utc>) -> Result<Tixel, ResolutionError> {
+    let query = sql!(
+      self.db,
+      "SELECT t.cid, t.data, t.blob_key
+      FROM Tixels t
+      JOIN Strands s ON t.strand = s.id
+      WHERE s.cid = ?1 AND t.timestamp_ms <= ?2
+      ORDER BY t.timestamp_ms DESC, t.idx DESC
+      LIMIT 1;",
+      strand_cid.to_bytes(),
+      at.timestamp_millis()
+    );
+    let result = self.db.first::<BlockRecord>(query, None).await.map_err(to_resolution_error)?;
+    let block = result.ok_or(ResolutionError::NotFound)?;
+    self.load_tixel(block).await
+  }
+
+  /// Tixels of a strand with times from `from` up to and including `to`, in index order.
+  ///
+  /// Pages by tixel index, starting after the `after` index if given.
+  pub async fn tixels_between(&self, strand_cid: &Cid, from: DateTime<Utc>, to: DateTime<Utc>, after: Option<u64>, limit: u64) -> Result<(Vec<Tixel>, Option<u64>), ResolutionError> {
+    let query = sql!(
+      self.db,
+      "SELECT t.cid, t.data, t.blob_key
+      FROM Tixels t
+      JOIN Strands s ON t.strand = s.id
+      WHERE s.cid = ?1
+        AND t.timestamp_ms >= ?2 AND t.timestamp_ms <= ?3
+        AND t.idx > ?4
+      ORDER BY t.idx ASC
+      LIMIT ?5;",
+      strand_cid.to_bytes(),
+      from.timestamp_millis(),
+      to.timestamp_millis(),
+      after.map(|i| i as i64).unwrap_or(-1),
+      limit as i64
+    );
+    let records = self.db.all::<BlockRecord>(query).await.map_err(to_resolution_error)?;
+    let mut tixels = Vec::with_capacity(records.len());
+    for record in records {
+      tixels.push(self.load_tixel(record).await?);
+    }
+    let next = if tixels.len() as u64 >= limit {
+      tixels.last().map(|t| t.index())
+    } else {
+      None
+    };
+    Ok((tixels, next))
+  }
+
+  /// Tixels in one range of a strand, each decoded separately.
+  ///
+  /// Ranges should span at most [`BATCH_SIZE`] tixels.
+  pub async fn tixel_batch(&self, range: &AbsoluteRange) -> Result<Vec<Result<Tixel, ResolutionError>>, ResolutionError> {
+    let dir = if range.is_increasing() { "ASC" } else { "DESC" };
+    let query = sql!(
+      self.db,
+      &format!("
+        SELECT t.cid, t.data, t.blob_key
+        FROM Tixels t JOIN Strands s ON t.strand = s.id
+        WHERE s.cid = ?1 AND t.idx >= ?2 AND t.idx <= ?3
+        ORDER BY t.idx {}
+      ", dir),
+      range.strand.to_bytes(),
+      range.lower() as i64,
+      range.upper() as i64
+    );
+
+    let blocks = self.db.all::<BlockRecord>(query)
+      .await
+      .map_err(to_resolution_error)?;
+    let mut tixels = Vec::with_capacity(blocks.len());
+    for block in blocks {
+      tixels.push(self.load_tixel(block).await);
+    }
+
+    Ok(tixels)
+  }
+
+  /// Stitches from stored tixels to a strand, or to one tixel of it.
+  ///
+  /// Pages through results by the `after` cursor like [`Self::strands_page`].
+  pub async fn stitches_to(&self, strand_cid: &Cid, tixel: Option<&Cid>, after: i64, limit: u64) -> Result<(Vec<StitchRef>, Option<i64>), ResolutionError> {
+    let query = sql!(
+      self.db,
+      &format!(
+        "{} WHERE st.to_strand = ?1 AND (?2 IS NULL OR st.to_tixel = ?2) AND st.rowid > ?3 ORDER BY st.rowid LIMIT ?4",
+        STITCH_QUERY
+      ),
+      strand_cid.to_bytes(),
+      tixel.map(|c| c.to_bytes()),
+      after,
+      limit as i64
+    );
+    let records = self.db.all::<StitchRecord>(query).await.map_err(to_resolution_error)?;
+    let next = if records.len() as u64 >= limit {
+      records.last().map(|r| r.id)
+    } else {
+      None
+    };
+    let stitches = records.into_iter()
+      .map(|r| r.into_ref())
+      .collect::<Result<Vec<_>, _>>()?;
+    Ok((stitches, next))
+  }
+
+  /// Stitches of one stored tixel to other strands
+  pub async fn stitches_from(&self, strand_cid: &Cid, index: u64) -> Result<Vec<StitchRef>, ResolutionError> {
+    let query = sql!(
+      self.db,
+      &format!("{} WHERE s.cid = ?1 AND st.from_idx = ?2", STITCH_QUERY),
+      strand_cid.to_bytes(),
+      index as i64
+    );
+    let records = self.db.all::<StitchRecord>(query).await.map_err(to_resolution_error)?;
+    records.into_iter().map(|r| r.into_ref()).collect()
+  }
+
+  /// Record the stitches of tixels stored before stitches were indexed
+  pub async fn reindex_stitches(&self, strand_cid: &Cid) -> Result<usize, StoreError> {
+    let latest = match self.strand_stats(strand_cid).await? {
+      Some(stats) => stats.latest_index,
+      None => return Ok(0),
+    };
+    let range = AbsoluteRange::new(*strand_cid, 0, latest);
+    let mut tixels = unchecked_base::BaseResolver::range_stream(self, range).await?
+      .try_chunks(WRITE_BATCH_SIZE);
+    let mut count = 0;
+    while let Some(chunk) = tixels.next().await {
+      let chunk = chunk.map_err(|e| e.1)?;
+      let statements: Vec<_> = chunk.iter().flat_map(|t| self.save_stitch_statements(t)).collect();
+      if statements.is_empty() {
+        continue;
+      }
+      let results = self.db.batch(statements).await.map_err(to_storage_error)?;
+      count += results.iter().map(|r| r.changes).sum::<usize>();
+    }
+    log::info!("Reindexed {} stitches of strand {}", count, strand_cid);
+    Ok(count)
+  }
+
+  /// Save twines in D1 batches of [`WRITE_BATCH_SIZE`].
+  ///
+  /// Each batch runs as a single transaction, and statements run in order,
+  /// so a tixel can rely on its predecessor from earlier in the same batch.
+  /// Once a twine is refused the later tixels of its strand are skipped,
+  /// and the twines of other strands are still saved.
+  pub async fn save_batch(&self, twines: Vec<AnyTwine>) -> Result<BatchReport, StoreError> {
+    self.save_batch_refusing(twines, &mut RefusedStrands::default()).await
+  }
+
+  /// [`Self::save_batch`], also skipping the tixels `refused` skips and
+  /// adding the twines it refuses, so that refusals carry over between
+  /// batches.
+  pub async fn save_batch_refusing(&self, twines: Vec<AnyTwine>, refused: &mut RefusedStrands) -> Result<BatchReport, StoreError> {
+    let strands = self.batch_strands(&twines).await?;
+    let mut report = BatchReport::default();
+    let mut outcomes: Vec<Option<Outcome>> = vec![None; twines.len()];
+
+    // strands must exist before their tixels, and tixels must be in index order
+    let mut order: Vec<usize> = (0..twines.len()).collect();
+    order.sort_by_key(|&i| match &twines[i] {
+      AnyTwine::Strand(_) => (0, 0),
+      AnyTwine::Tixel(t) => (1, t.index()),
+    });
+    // tixels that can be refused without writing anything
+    let mut pending = Vec::with_capacity(order.len());
+    for i in order {
+      if refused.skips(&twines[i]) {
+        outcomes[i] = Some(Outcome::Skipped);
+        continue;
+      }
+      if let AnyTwine::Tixel(t) = &twines[i] {
+        let reason = match strands.get(&t.strand_cid()) {
+          None => Some(WriteError::UnknownStrand),
+          Some(strand) => verify_tixel(strand, t).err().map(|e| {
+            log::debug!("Tixel {} failed verification: {}", t.cid(), e);
+            WriteError::InvalidSignature(e)
+          }),
+        };
+        if let Some(reason) = reason {
+          refused.refuse(&twines[i]);
+          outcomes[i] = Some(report.refuse(i, reason));
+          continue;
+        }
+      }
+      pending.push(i);
+    }
+
+    for chunk in pending.chunks(WRITE_BATCH_SIZE) {
+      // tixels after one refused in an earlier batch
+      let chunk: Vec<usize> = chunk.iter()
+        .copied()
+        .filter(|&i| if refused.skips(&twines[i]) {
+          outcomes[i] = Some(Outcome::Skipped);
+          false
+        } else {
+          true
+        })
+        .collect();
+      if chunk.is_empty() {
+        continue;
+      }
+      let mut blob_keys = HashMap::new();
+      for &i in &chunk {
+        if let AnyTwine::Tixel(t) = &twines[i] {
+          if let Some(key) = self.offload(t).await? {
+            blob_keys.insert(t.cid(), key);
+          }
+        }
+      }
+      let groups: Vec<Vec<Statement>> = chunk.iter()
+        .map(|&i| match &twines[i] {
+          AnyTwine::Strand(s) => vec![self.save_strand_statement(s)],
+          AnyTwine::Tixel(t) => self.save_tixel_statements(
+            t,
+            &self.timestamp_field_of(&strands[&t.strand_cid()]),
+            blob_keys.get(&t.cid()).map(String::as_str),
+          ),
+        })
+        .collect();
+      // where the insert of each twine lands among the batch results
+      let offsets: Vec<usize> = groups.iter()
+        .scan(0, |next, group| {
+          let offset = *next;
+          *next += group.len();
+          Some(offset)
+        })
+        .collect();
+      let results = self.db.batch(groups.into_iter().flatten().collect()).await.map_err(to_storage_error)?;
+
+      let mut orphaned = Vec::new();
+      // strands that may have staged tixels ready to promote
+      let mut advanced = HashSet::new();
+      for (&i, offset) in chunk.iter().zip(offsets) {
+        let outcome = match &twines[i] {
+          AnyTwine::Tixel(t) if results[offset].changes > 0 => {
+            advanced.insert(t.strand_cid());
+            Outcome::Saved
+          },
+          _ if results[offset].changes > 0 => Outcome::Saved,
+          AnyTwine::Strand(s) if self.is_tombstoned(&s.cid()).await? => {
+            refused.refuse(&twines[i]);
+            report.refuse(i, WriteError::Removed)
+          },
+          AnyTwine::Strand(_) => Outcome::Duplicate,
+          // after a refused tixel of its strand in this batch
+          AnyTwine::Tixel(t) if refused.skips(&twines[i]) => {
+            orphaned.extend(blob_keys.remove(&t.cid()));
+            Outcome::Skipped
+          },
+          AnyTwine::Tixel(t) => match self.rejection_reason(t).await? {
+            WriteError::Duplicate => Outcome::Duplicate,
+            WriteError::Gap if self.stage_tixel(t, blob_keys.get(&t.cid()).map(String::as_str)).await? => {
+              advanced.insert(t.strand_cid());
+              Outcome::Staged
+            },
+            WriteError::Store(e) => return Err(e),
+            reason => {
+              if matches!(reason, WriteError::Fork) {
+                self.record_equivocation(t).await?;
+              }
+              log::info!("Tixel {}:{} refused: {}", t.strand_cid(), t.index(), reason);
+              orphaned.extend(blob_keys.remove(&t.cid()));
+              refused.refuse(&twines[i]);
+              report.refuse(i, reason)
+            },
+          },
+        };
+        outcomes[i] = Some(outcome);
+      }
+      self.delete_blobs(&orphaned).await;
+      for strand_cid in advanced {
+        self.promote_pending(&strands[&strand_cid]).await?;
+      }
+      log::debug!("Saved batch of {} twines", chunk.len());
+    }
+
+    report.outcomes = twines.iter()
+      .zip(outcomes)
+      .map(|(twine, outcome)| (twine.cid(), outcome.unwrap_or(Outcome::Skipped)))
+      .collect();
+    Ok(report)
+  }
+
+  /// Save twines from a stream with [`Self::save_batch`], [`WRITE_BATCH_SIZE`]
+  /// at a time, and report what came of each. Once a twine is refused the
+  /// later tixels of its strand are skipped.
+  pub async fn save_stream_with_report<T: Stream<Item = AnyTwine> + Unpin>(&self, twines: T) -> Result<BatchReport, StoreError> {
+    let mut report = BatchReport::default();
+    let mut refused = RefusedStrands::default();
+    let mut chunks = twines.chunks(WRITE_BATCH_SIZE);
+    while let Some(chunk) = chunks.next().await {
+      let batch = self.save_batch_refusing(chunk, &mut refused).await?;
+      report.outcomes.extend(batch.outcomes);
+      if report.refusal.is_none() {
+        report.refusal = batch.refusal;
+      }
+    }
+    log::info!(
+      "Saved twines: {} inserted, {} ignored",
+      report.count(Outcome::Saved),
+      report.count(Outcome::Duplicate)
+    );
+    Ok(report)
+  }
+
+  pub async fn strand_status(&self, cid: &Cid) -> Result<Option<StrandStatus>, ResolutionError> {
+    let query = sql!(
+      self.db,
+      "SELECT writable, frozen_reason, frozen_at, equivocated, write_rate_limit FROM Strands WHERE cid = ?1",
+      cid.to_bytes()
+    );
+    let record = self.db.first::<StrandStatusRecord>(query, None).await.map_err(to_resolution_error)?;
+    Ok(record.map(|r| StrandStatus {
+      cid: cid.to_string(),
+      writable: r.writable != 0,
+      frozen_reason: r.frozen_reason,
+      frozen_at: r.frozen_at,
+      equivocated: r.equivocated != 0,
+      write_rate_limit: r.write_rate_limit,
+    }))
+  }
+
+  /// Whether tixels of a strand may be written on the strength of their
+  /// signatures alone, which takes a registered and approved strand that is
+  /// stored and writable.
+  ///
+  /// Returns the strand's own rate limit if it may, as `Some(limit)`.
+  pub async fn signed_write_limit(&self, cid: &Cid) -> Result<Option<Option<u32>>, ResolutionError> {
+    let query = sql!(
+      self.db,
+      "SELECT s.write_rate_limit
+      FROM Strands s
+      JOIN Registrations r ON r.strand_cid = s.cid
+      WHERE s.cid = ?1 AND s.writable = 1 AND r.status = 'Approved';",
+      cid.to_bytes()
+    );
+    let record = self.db.first::<SignedWriteRecord>(query, None).await.map_err(to_resolution_error)?;
+    Ok(record.map(|r| r.write_rate_limit))
+  }
+
+  /// Set how many writes a minute a strand may make without an api key,
+  /// or go back to the default.
+  ///
+  /// Returns false if the strand doesn't exist.
+  pub async fn set_write_rate_limit(&self, cid: &Cid, per_minute: Option<u32>) -> Result<bool, StoreError> {
+    let query = sql!(
+      self.db,
+      "UPDATE Strands SET write_rate_limit = ?2 WHERE cid = ?1",
+      cid.to_bytes(),
+      per_minute
+    );
+    let result = self.db.run(query).await.map_err(to_storage_error)?;
+    Ok(result.changes > 0)
+  }
+
+  /// Freeze a strand so it accepts no more tixels, recording why.
+  ///
+  /// Returns false if the strand doesn't exist.
+  pub async fn freeze_strand(&self, cid: &Cid, reason: &str) -> Result<bool, StoreError> {
+    let query = sql!(
+      self.db,
+      "UPDATE Strands SET writable = 0, frozen_reason = ?2, frozen_at = ?3 WHERE cid = ?1",
+      cid.to_bytes(),
+      reason,
+      Utc::now().naive_utc()
+    );
+    let result = self.db.run(query).await.map_err(to_storage_error)?;
+    if result.changes > 0 {
+      log::info!("Strand frozen: {} ({})", cid, reason);
+    }
+    Ok(result.changes > 0)
+  }
+
+  /// Reopen a frozen strand for writing.
+  ///
+  /// Returns false if the strand doesn't exist.
+  pub async fn unfreeze_strand(&self, cid: &Cid) -> Result<bool, StoreError> {
+    let query = sql!(
+      self.db,
+      "UPDATE Strands SET writable = 1, frozen_reason = NULL, frozen_at = NULL WHERE cid = ?1",
+      cid.to_bytes()
+    );
+    let result = self.db.run(query).await.map_err(to_storage_error)?;
+    if result.changes > 0 {
+      log::info!("Strand reopened: {}", cid);
+    }
+    Ok(result.changes > 0)
+  }
+
+  async fn remove_strand(&self, cid: &Cid) -> Result<(), StoreError> {
+    let query = sql!(
+      self.db,
+      "SELECT t.blob_key
+      FROM Tixels t
+      JOIN Strands s ON t.strand = s.id
+      WHERE s.cid = ?1 AND t.blob_key IS NOT NULL
+      UNION ALL
+      SELECT p.blob_key
+      FROM PendingTixels p
+      JOIN Strands s ON p.strand = s.id
+      WHERE s.cid = ?1 AND p.blob_key IS NOT NULL;",
+      cid.to_bytes()
+    );
+    let blob_keys: Vec<String> = self.db.all::<Row>(query).await
+      .map_err(to_storage_error)?
+      .into_iter()
+      .filter_map(|row| row.get("blob_key")?.as_str().map(String::from))
+      .collect();
+
+    // everything goes in one batch so a strand is never left half removed
+    let cid_bytes = cid.to_bytes();
+    let statements = vec![
+      sql!(
+        self.db,
+        "DELETE FROM Tixels WHERE strand = (SELECT id FROM Strands WHERE cid = ?1);",
+        cid_bytes
+      ),
+      sql!(
+        self.db,
+        "DELETE FROM Equivocations WHERE strand = (SELECT id FROM Strands WHERE cid = ?1);",
+        cid_bytes
+      ),
+      sql!(
+        self.db,
+        "DELETE FROM PendingTixels WHERE strand = (SELECT id FROM Strands WHERE cid = ?1);",
+        cid_bytes
+      ),
+      sql!(
+        self.db,
+        "DELETE FROM StrandStats WHERE strand = (SELECT id FROM Strands WHERE cid = ?1);",
+        cid_bytes
+      ),
+      sql!(
+        self.db,
+        "DELETE FROM Stitches WHERE from_strand = (SELECT id FROM Strands WHERE cid = ?1);",
+        cid_bytes
+      ),
+      sql!(self.db, "DELETE FROM Registrations WHERE strand_cid = ?1;", cid_bytes),
+      sql!(self.db, "DELETE FROM Strands WHERE cid = ?1;", cid_bytes),
+      // leave a tombstone so the strand can't be registered again
+      sql!(self.db, "INSERT OR IGNORE INTO StrandTombstones (cid) VALUES (?1);", cid_bytes),
+    ];
+    self.db.batch(statements).await.map_err(to_storage_error)?;
+    self.delete_blobs(&blob_keys).await;
+    log::info!("Strand removed: {}", cid);
+    Ok(())
+  }
+
+  /// Remove a stored strand, or a tixel if it is the latest of its strand
+  /// The strand of a stored twine: the cid itself for a strand, or the
+  /// strand of a tixel
+  pub async fn strand_of(&self, cid: &Cid) -> Result<Option<Cid>, ResolutionError> {
+    let query = sql!(
+      self.db,
+      "SELECT cid FROM Strands WHERE cid = ?1
+      UNION ALL
+      SELECT s.cid FROM Tixels t JOIN Strands s ON t.strand = s.id WHERE t.cid = ?1
+      LIMIT 1;",
+      cid.to_bytes()
+    );
+    let strand = self.db.first::<Vec<u8>>(query, Some("cid")).await.map_err(to_resolution_error)?;
+    Ok(strand
+      .map(|bytes| Cid::try_from(bytes).map_err(|e| VerificationError::General(e.to_string())))
+      .transpose()?)
+  }
+
+  pub async fn remove(&self, cid: &Cid) -> Result<(), WriteError> {
+    if self.has_strand_cid(cid).await.map_err(StoreError::from)? {
+      return Ok(self.remove_strand(cid).await?);
+    }
+    self.remove_tixel_if_latest(cid).await
+  }
+
+  pub async fn remove_tixel_if_latest(&self, cid: &Cid) -> Result<(), WriteError> {
+    let query = sql!(
+      self.db,
+      "SELECT t.strand, t.blob_key, t.idx, s.writable, (SELECT MAX(idx) FROM Tixels WHERE strand = t.strand) AS latest
+      FROM Tixels t
+      JOIN Strands s ON t.strand = s.id
+      WHERE t.cid = ?1;",
+      cid.to_bytes()
+    );
+    let record = self.db.first::<TixelHeadRecord>(query, None).await.map_err(to_storage_error)?;
+    let record = match record {
+      Some(record) => record,
+      None => return Err(WriteError::UnknownTixel),
+    };
+    if record.writable == 0 {
+      return Err(WriteError::NotWritable);
+    }
+    if record.idx != record.latest {
+      return Err(WriteError::NotLatest);
+    }
+
+    // the conditions are checked again here in case the strand
+    // was written to in the meantime
+    let statements = vec![
+      sql!(
+        self.db,
+        "DELETE FROM Tixels
+        WHERE cid = ?1
+          AND idx = (SELECT MAX(t.idx) FROM Tixels t WHERE t.strand = Tixels.strand)
+          AND EXISTS (SELECT 1 FROM Strands s WHERE s.id = Tixels.strand AND s.writable = 1);",
+        cid.to_bytes()
+      ),
+      sql!(
+        self.db,
+        "DELETE FROM Stitches
+        WHERE from_strand = ?1
+          AND from_idx > (SELECT COALESCE(MAX(idx), -1) FROM Tixels WHERE strand = ?1);",
+        record.strand
+      ),
+      // recount from what is left of the strand
+      sql!(
+        self.db,
+        "UPDATE StrandStats SET
+          latest_idx = (SELECT MAX(idx) FROM Tixels WHERE strand = ?1),
+          latest_cid = (SELECT cid FROM Tixels WHERE strand = ?1 ORDER BY idx DESC LIMIT 1),
+          tixel_count = (SELECT COUNT(*) FROM Tixels WHERE strand = ?1),
+          total_bytes = (SELECT SUM(COALESCE(size, LENGTH(data))) FROM Tixels WHERE strand = ?1)
+        WHERE strand = ?1 AND EXISTS (SELECT 1 FROM Tixels WHERE strand = ?1);",
+        record.strand
+      ),
+      sql!(
+        self.db,
+        "DELETE FROM StrandStats WHERE strand = ?1 AND NOT EXISTS (SELECT 1 FROM Tixels WHERE strand = ?1);",
+        record.strand
+      ),
+    ];
+    let results = self.db.batch(statements).await.map_err(to_storage_error)?;
+    if results[0].changes == 0 {
+      return Err(WriteError::NotLatest);
+    }
+    if let Some(key) = record.blob_key {
+      self.delete_blobs(&[key]).await;
+    }
+    log::info!("Tixel removed from head of strand: {}", cid);
+    Ok(())
   }
 }
 
-#[async_trait(?Send)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
 impl unchecked_base::BaseResolver for D1Store {
 
-  async fn fetch_strands(&self) -> Result<Pin<Box<dyn Stream<Item = Result<Strand, ResolutionError>> + '_>>, ResolutionError> {
+  async fn fetch_strands(&self) -> Result<TwineStream<'_, Strand>, ResolutionError> {
     self.all_strands().await
   }
 
@@ -234,25 +1626,19 @@ impl unchecked_base::BaseResolver for D1Store {
   }
 
   async fn has_index(&self, strand: &Cid, index: u64) -> Result<bool, ResolutionError> {
-    self.cid_for_index(strand, index).await.map(|_| true).or_else(|e| {
-      if let ResolutionError::NotFound = e {
-        Ok(false)
-      } else {
-        Err(e)
-      }
-    })
+    self.has_index_up_to_latest(strand, index).await
   }
 
-  async fn has_twine(&self, _strand: &Cid, cid: &Cid) -> Result<bool, ResolutionError> {
-    self.has_tixel(cid).await
+  async fn has_twine(&self, strand: &Cid, cid: &Cid) -> Result<bool, ResolutionError> {
+    self.has_tixel_in_strand(strand, cid).await
   }
 
   async fn fetch_strand(&self, strand: &Cid) -> Result<Strand, ResolutionError> {
     self.get_strand(strand).await
   }
 
-  async fn fetch_tixel(&self, _strand: &Cid, tixel: &Cid) -> Result<Tixel, ResolutionError> {
-    self.get_tixel(tixel).await
+  async fn fetch_tixel(&self, strand: &Cid, tixel: &Cid) -> Result<Tixel, ResolutionError> {
+    self.get_tixel_in_strand(strand, tixel).await
   }
 
   async fn fetch_index(&self, strand: &Cid, index: u64) -> Result<Tixel, ResolutionError> {
@@ -263,42 +1649,13 @@ impl unchecked_base::BaseResolver for D1Store {
     self.latest_tixel(strand).await
   }
 
-  async fn range_stream(&self, range: AbsoluteRange) -> Result<Pin<Box<dyn Stream<Item = Result<Tixel, ResolutionError>> + '_>>, ResolutionError> {
+  async fn range_stream(&self, range: AbsoluteRange) -> Result<TwineStream<'_, Tixel>, ResolutionError> {
     let batches = range.batches(BATCH_SIZE);
 
-    async fn get_batch(db: &D1Database, range: &AbsoluteRange) -> Result<Vec<Result<Tixel, ResolutionError>>, ResolutionError> {
-      let dir = if range.is_increasing() { "ASC" } else { "DESC" };
-      let query = query!(
-        &db,
-        &format!("
-          SELECT t.cid, t.data
-          FROM Tixels t JOIN Strands s ON t.strand = s.id
-          WHERE s.cid = ?1 AND t.idx >= ?2 AND t.idx <= ?3
-          ORDER BY t.idx {}
-        ", dir),
-        range.strand.to_bytes(),
-        range.lower() as i64,
-        range.upper() as i64
-      ).map_err(to_resolution_error)?;
-
-      let results = query.all().await.map_err(to_resolution_error)?;
-      let tixels = results
-        .results::<BlockRecord>()
-        .map_err(to_resolution_error)?
-        .into_iter()
-        .map(|block| {
-          let cid = Cid::try_from(block.cid).map_err(|e| ResolutionError::Fetch(e.to_string()))?;
-          Ok(Tixel::from_block(cid, block.data)?)
-        })
-        .collect::<Vec<_>>();
-
-      Ok(tixels)
-    }
-
     let stream = unfold(batches.into_iter(), move |mut batches| {
       async move {
         let batch = batches.next()?;
-        let tixels = match get_batch(&self.db, &batch).await {
+        let tixels = match self.tixel_batch(&batch).await {
           Ok(tixels) => tixels,
           Err(e) => return Some((Err(e), batches)),
         };
@@ -309,47 +1666,449 @@ impl unchecked_base::BaseResolver for D1Store {
       }
     })
     .map_ok(|v| futures::stream::iter(v.into_iter()))
-    .try_flatten()
-    .boxed_local();
+    .try_flatten();
 
-    Ok(stream)
+    Ok(Box::pin(stream))
   }
 }
 
 impl Resolver for D1Store {}
 
-#[async_trait(?Send)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
 impl Store for D1Store {
-  async fn save<T: Into<AnyTwine>>(&self, twine: T) -> Result<(), StoreError> {
+  async fn save<T: Into<AnyTwine> + MaybeSend>(&self, twine: T) -> Result<(), StoreError> {
     match twine.into() {
-      AnyTwine::Tixel(t) => self.save_tixel(&t).await,
-      AnyTwine::Strand(s) => self.save_strand(&s).await,
+      // saving what's already stored is not an error for store users
+      AnyTwine::Tixel(t) => match self.save_tixel(&t).await {
+        Ok(_) | Err(WriteError::Duplicate) => Ok(()),
+        Err(e) => Err(e.into()),
+      },
+      AnyTwine::Strand(s) => Ok(self.save_strand(&s).await?),
     }
   }
 
-  async fn save_many<I: Into<AnyTwine>, S: Iterator<Item = I>, T: IntoIterator<Item = I, IntoIter = S>>(&self, twines: T) -> Result<(), StoreError> {
-    for twine in twines {
-      self.save(twine).await?;
+  async fn save_many<I: Into<AnyTwine> + MaybeSend, S: Iterator<Item = I> + MaybeSend, T: IntoIterator<Item = I, IntoIter = S> + MaybeSend>(&self, twines: T) -> Result<(), StoreError> {
+    let twines = twines.into_iter().map(|t| t.into()).collect();
+    let report = self.save_batch(twines).await?;
+    log::info!(
+      "Saved twines: {} inserted, {} ignored",
+      report.count(Outcome::Saved),
+      report.count(Outcome::Duplicate)
+    );
+    match report.refusal {
+      Some(reason) => Err(reason.into()),
+      None => Ok(()),
     }
-    Ok(())
   }
 
-  async fn save_stream<I: Into<AnyTwine>, T: Stream<Item = I> + Unpin>(&self, twines: T) -> Result<(), StoreError> {
-    twines
-      .chunks(100)
-      .then(|chunk| self.save_many(chunk))
-      .try_for_each(|_| async { Ok(()) })
-      .await?;
-    Ok(())
+  async fn save_stream<I: Into<AnyTwine> + MaybeSend, T: Stream<Item = I> + MaybeSend + Unpin>(&self, twines: T) -> Result<(), StoreError> {
+    let report = self.save_stream_with_report(twines.map(|t| t.into())).await?;
+    match report.refusal {
+      Some(reason) => Err(reason.into()),
+      None => Ok(()),
+    }
   }
 
-  async fn delete<C: AsCid>(&self, cid: C) -> Result<(), StoreError> {
-    if self.has_strand_cid(cid.as_cid()).await? {
-      self.remove_strand(cid.as_cid()).await
-    } else if self.has_tixel(cid.as_cid()).await? {
-      self.remove_tixel_if_latest(cid.as_cid()).await
-    } else {
-      Ok(())
+  async fn delete<C: AsCid + MaybeSend>(&self, cid: C) -> Result<(), StoreError> {
+    Ok(self.remove(cid.as_cid()).await?)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::backend::test_backend;
+  use futures::executor::block_on;
+  use twine_protocol::twine_builder::{RingSigner, TwineBuilder};
+  use twine_protocol::twine_lib::ipld_core::ipld;
+
+  fn builder() -> TwineBuilder<2, RingSigner> {
+    TwineBuilder::new(RingSigner::generate_ed25519().unwrap())
+  }
+
+  fn chain(builder: &TwineBuilder<2, RingSigner>, strand: &Strand, len: usize) -> Vec<Twine> {
+    let mut tixels: Vec<Twine> = vec![builder.build_first(strand.clone()).payload(ipld!({ "n": 0 })).done().unwrap()];
+    for n in 1..len {
+      let next = builder.build_next(tixels.last().unwrap()).payload(ipld!({ "n": n })).done().unwrap();
+      tixels.push(next);
     }
+    tixels
+  }
+
+  #[test]
+  fn saves_and_fetches_twines() {
+    let store = D1Store::with_backend(test_backend());
+    let builder = builder();
+    let strand = builder.build_strand().details(ipld!({ "name": "test" })).done().unwrap();
+    let tixels = chain(&builder, &strand, 3);
+    block_on(async {
+      store.save(strand.clone()).await.unwrap();
+      for tixel in &tixels {
+        assert_eq!(store.save_tixel(tixel).await.unwrap(), Outcome::Saved);
+      }
+      assert_eq!(store.get_strand(&strand.cid()).await.unwrap(), strand);
+      assert_eq!(store.get_tixel(&tixels[1].cid()).await.unwrap(), *tixels[1].tixel());
+      assert_eq!(store.get_tixel_by_index(&strand.cid(), 2).await.unwrap(), *tixels[2].tixel());
+      assert_eq!(store.latest_tixel(&strand.cid()).await.unwrap(), *tixels[2].tixel());
+      let stats = store.strand_stats(&strand.cid()).await.unwrap().unwrap();
+      assert_eq!(stats.tixel_count, 3);
+    });
+  }
+
+  #[test]
+  fn refuses_tixels_that_dont_connect() {
+    let store = D1Store::with_backend(test_backend());
+    let builder = builder();
+    let strand = builder.build_strand().done().unwrap();
+    let tixels = chain(&builder, &strand, 3);
+    block_on(async {
+      assert!(matches!(store.save_tixel(&tixels[0]).await, Err(WriteError::UnknownStrand)));
+      store.save(strand.clone()).await.unwrap();
+      store.save_tixel(&tixels[0]).await.unwrap();
+      assert!(matches!(store.save_tixel(&tixels[0]).await, Err(WriteError::Duplicate)));
+      store.save(tixels[0].clone()).await.unwrap();
+      assert_eq!(store.save_tixel(&tixels[2]).await.unwrap(), Outcome::Staged);
+      assert!(matches!(store.get_tixel(&tixels[2].cid()).await, Err(ResolutionError::NotFound)));
+      // the staged tixel is promoted once the gap is filled
+      store.save_tixel(&tixels[1]).await.unwrap();
+      assert_eq!(store.latest_tixel(&strand.cid()).await.unwrap(), *tixels[2].tixel());
+    });
+  }
+
+  #[test]
+  fn removes_strands_for_good() {
+    use crate::registration::RegistrationRecord;
+    let store = D1Store::with_backend(test_backend());
+    let builder = builder();
+    let strand = builder.build_strand().done().unwrap();
+    let tixels = chain(&builder, &strand, 2);
+    let email = serde_email::Email::from_string("someone@example.com".to_string()).unwrap();
+    block_on(async {
+      RegistrationRecord::new_preapproved(email, strand.cid(), strand.clone()).save(store.db.as_ref()).await.unwrap();
+      store.save(strand.clone()).await.unwrap();
+      store.save_many(tixels.clone()).await.unwrap();
+      store.remove(&strand.cid()).await.unwrap();
+      assert!(!store.has_strand_cid(&strand.cid()).await.unwrap());
+      assert!(matches!(store.get_tixel(&tixels[0].cid()).await, Err(ResolutionError::NotFound)));
+      assert!(RegistrationRecord::check_approved(store.db.as_ref(), &strand).await.unwrap().is_none());
+      assert!(store.is_tombstoned(&strand.cid()).await.unwrap());
+      assert!(matches!(store.save_strand(&strand).await, Err(WriteError::Removed)));
+      let report = store.save_batch(vec![strand.clone().into()]).await.unwrap();
+      assert_eq!(report.outcomes[0].1, Outcome::Refused("removed"));
+      assert!(matches!(report.refusal, Some(WriteError::Removed)));
+      assert!(!store.has_strand_cid(&strand.cid()).await.unwrap());
+    });
+  }
+
+  #[test]
+  fn removes_only_the_latest_tixel() {
+    let store = D1Store::with_backend(test_backend());
+    let builder = builder();
+    let strand = builder.build_strand().done().unwrap();
+    let tixels = chain(&builder, &strand, 2);
+    block_on(async {
+      store.save(strand.clone()).await.unwrap();
+      for tixel in &tixels {
+        store.save_tixel(tixel).await.unwrap();
+      }
+      assert!(matches!(store.remove(&tixels[0].cid()).await, Err(WriteError::NotLatest)));
+      store.remove(&tixels[1].cid()).await.unwrap();
+      assert!(matches!(store.remove(&tixels[1].cid()).await, Err(WriteError::UnknownTixel)));
+      assert_eq!(store.latest_tixel(&strand.cid()).await.unwrap(), *tixels[0].tixel());
+    });
+  }
+
+  #[test]
+  fn searches_specs_by_literal_prefix() {
+    let store = D1Store::with_backend(test_backend());
+    let builder = builder();
+    let literal = builder.build_strand().subspec("a_b/1.0.0".to_string()).done().unwrap();
+    let wildcard = builder.build_strand().subspec("axb/1.0.0".to_string()).done().unwrap();
+    let prefix = literal.spec_str().trim_end_matches("/1.0.0").to_string();
+    block_on(async {
+      store.save(literal.clone()).await.unwrap();
+      store.save(wildcard).await.unwrap();
+      let search = StrandSearch { spec: Some(prefix), ..Default::default() };
+      let (found, _) = store.search_strands(&search, 0, 10).await.unwrap();
+      assert_eq!(found.into_iter().map(|(s, _)| s).collect::<Vec<_>>(), vec![literal]);
+    });
+  }
+
+  #[test]
+  fn pages_strands_by_cursor() {
+    let store = D1Store::with_backend(test_backend());
+    let builder = builder();
+    let strands: Vec<Strand> = (0..3).map(|_| builder.build_strand().done().unwrap()).collect();
+    block_on(async {
+      store.save_many(strands.clone()).await.unwrap();
+      let (first, after) = store.strands_page(0, 2).await.unwrap();
+      assert_eq!(first.len(), 2);
+      let after = after.unwrap();
+      let (rest, next) = store.strands_page(after, 2).await.unwrap();
+      assert_eq!(rest.len(), 1);
+      assert_eq!(next, None);
+      let mut seen: Vec<Cid> = first.into_iter().chain(rest).map(|(s, _)| s.cid()).collect();
+      let mut expected: Vec<Cid> = strands.iter().map(|s| s.cid()).collect();
+      seen.sort();
+      expected.sort();
+      assert_eq!(seen, expected);
+    });
+  }
+
+  #[test]
+  fn searches_details_by_path() {
+    let store = D1Store::with_backend(test_backend());
+    let builder = builder();
+    let tagged = builder.build_strand()
+      .details(ipld!({ "name": "a", "owner": { "id": 7 }, "tags": ["x", "y"] }))
+      .done()
+      .unwrap();
+    let other = builder.build_strand().details(ipld!({ "name": "b" })).done().unwrap();
+    block_on(async {
+      store.save_many(vec![tagged.clone(), other.clone()]).await.unwrap();
+      let search = |details: &[(&str, &str)]| StrandSearch {
+        details: details.iter().map(|(p, v)| (p.to_string(), v.to_string())).collect(),
+        ..Default::default()
+      };
+      let store = &store;
+      let found = |search: StrandSearch| async move {
+        let (found, _) = store.search_strands(&search, 0, 10).await.unwrap();
+        found.into_iter().map(|(s, _)| s.cid()).collect::<Vec<_>>()
+      };
+      assert_eq!(found(search(&[("name", "b")])).await, vec![other.cid()]);
+      assert_eq!(found(search(&[("owner.id", "7")])).await, vec![tagged.cid()]);
+      assert_eq!(found(search(&[("tags", "y")])).await, vec![tagged.cid()]);
+      assert_eq!(found(search(&[("name", "a"), ("tags", "z")])).await, vec![]);
+    });
+  }
+
+  #[test]
+  fn indexes_stitches_both_ways() {
+    let store = D1Store::with_backend(test_backend());
+    let builder = builder();
+    let target = builder.build_strand().done().unwrap();
+    let source = builder.build_strand().done().unwrap();
+    let stitched = builder.build_first(target.clone()).done().unwrap();
+    let stitching = builder.build_first(source.clone())
+      .cross_stitches(vec![(target.cid(), stitched.cid())])
+      .done()
+      .unwrap();
+    block_on(async {
+      store.save_many(vec![target.clone(), source.clone()]).await.unwrap();
+      store.save_many(vec![stitched.clone(), stitching.clone()]).await.unwrap();
+      let expected = StitchRef {
+        from_strand: source.cid().to_string(),
+        from_index: 0,
+        from_tixel: stitching.cid().to_string(),
+        to_strand: target.cid().to_string(),
+        to_tixel: stitched.cid().to_string(),
+      };
+      let (to, next) = store.stitches_to(&target.cid(), None, 0, 10).await.unwrap();
+      assert_eq!(to, vec![expected.clone()]);
+      assert_eq!(next, None);
+      let (to_tixel, _) = store.stitches_to(&target.cid(), Some(&stitched.cid()), 0, 10).await.unwrap();
+      assert_eq!(to_tixel, vec![expected.clone()]);
+      let (to_other, _) = store.stitches_to(&target.cid(), Some(&stitching.cid()), 0, 10).await.unwrap();
+      assert!(to_other.is_empty());
+      assert_eq!(store.stitches_from(&source.cid(), 0).await.unwrap(), vec![expected.clone()]);
+      // tixels stored before stitches were indexed
+      store.db.run(sql!(store.db, "DELETE FROM Stitches")).await.unwrap();
+      assert_eq!(store.reindex_stitches(&source.cid()).await.unwrap(), 1);
+      assert_eq!(store.stitches_from(&source.cid(), 0).await.unwrap(), vec![expected]);
+    });
+  }
+
+  #[test]
+  fn finds_tixels_by_time() {
+    let store = D1Store::with_backend(test_backend());
+    let builder = builder();
+    let strand = builder.build_strand().done().unwrap();
+    let first = builder.build_first(strand.clone())
+      .payload(ipld!({ "timestamp": "2026-01-01T00:00:00Z" }))
+      .done()
+      .unwrap();
+    let second = builder.build_next(&first)
+      .payload(ipld!({ "timestamp": 1767229200 }))
+      .done()
+      .unwrap();
+    let third = builder.build_next(&second)
+      .payload(ipld!({ "timestamp": "2026-01-01T02:00:00+00:00" }))
+      .done()
+      .unwrap();
+    let time = |s: &str| DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc);
+    assert_eq!(tixel_timestamp(&second, "timestamp"), Some(1767229200 * 1000));
+    assert_eq!(tixel_timestamp(&second, "missing"), None);
+    block_on(async {
+      store.save(strand.clone()).await.unwrap();
+      store.save_many(vec![first.clone(), second.clone(), third.clone()]).await.unwrap();
+      let at = store.tixel_at_time(&strand.cid(), time("2026-01-01T01:30:00Z")).await.unwrap();
+      assert_eq!(at, *second.tixel());
+      assert!(matches!(store.tixel_at_time(&strand.cid(), time("2025-12-31T23:00:00Z")).await, Err(ResolutionError::NotFound)));
+      let (from, to) = (time("2026-01-01T00:00:00Z"), time("2026-01-01T02:00:00Z"));
+      let (page, after) = store.tixels_between(&strand.cid(), from, to, None, 2).await.unwrap();
+      assert_eq!(page, vec![first.tixel().clone(), second.tixel().clone()]);
+      assert_eq!(after, Some(1));
+      let (page, after) = store.tixels_between(&strand.cid(), from, to, after, 2).await.unwrap();
+      assert_eq!(page, vec![third.tixel().clone()]);
+      assert_eq!(after, None);
+    });
+  }
+
+  #[test]
+  fn finds_tixels_only_in_their_own_strand() {
+    let store = D1Store::with_backend(test_backend());
+    let builder = builder();
+    let strand = builder.build_strand().done().unwrap();
+    let other = builder.build_strand().done().unwrap();
+    let tixel = builder.build_first(strand.clone()).done().unwrap();
+    block_on(async {
+      store.save(strand.clone()).await.unwrap();
+      store.save(other.clone()).await.unwrap();
+      store.save_tixel(&tixel).await.unwrap();
+      assert!(store.has_tixel_in_strand(&strand.cid(), &tixel.cid()).await.unwrap());
+      assert!(!store.has_tixel_in_strand(&other.cid(), &tixel.cid()).await.unwrap());
+      assert_eq!(store.get_tixel_in_strand(&strand.cid(), &tixel.cid()).await.unwrap(), *tixel.tixel());
+      assert!(matches!(store.get_tixel_in_strand(&other.cid(), &tixel.cid()).await, Err(ResolutionError::NotFound)));
+    });
+  }
+
+  #[test]
+  fn keeps_equivocations_after_removal() {
+    use crate::blob_store::MemoryBlobStore;
+    // the stored side lives in the blob store until it's removed
+    let store = D1Store::with_backend(test_backend()).with_blob_store(Arc::new(MemoryBlobStore::default()), 0);
+    let builder = builder();
+    let strand = builder.build_strand().done().unwrap();
+    let stored = builder.build_first(strand.clone()).payload(ipld!({ "n": 0 })).done().unwrap();
+    let conflicting = builder.build_first(strand.clone()).payload(ipld!({ "n": 1 })).done().unwrap();
+    block_on(async {
+      store.save(strand.clone()).await.unwrap();
+      store.save_tixel(&stored).await.unwrap();
+      assert!(matches!(store.save_tixel(&conflicting).await, Err(WriteError::Fork)));
+      store.remove(&stored.cid()).await.unwrap();
+      let pairs = store.equivocations(&strand.cid()).await.unwrap();
+      assert_eq!(pairs, vec![(stored.tixel().clone(), conflicting.tixel().clone())]);
+      assert!(store.strand_status(&strand.cid()).await.unwrap().unwrap().equivocated);
+    });
+  }
+
+  #[test]
+  fn offloads_tixel_data_to_blobs() {
+    use crate::blob_store::MemoryBlobStore;
+    let blobs = Arc::new(MemoryBlobStore::default());
+    let store = D1Store::with_backend(test_backend()).with_blob_store(blobs.clone(), 0);
+    let builder = builder();
+    let strand = builder.build_strand().done().unwrap();
+    let tixels = chain(&builder, &strand, 2);
+    block_on(async {
+      store.save(strand.clone()).await.unwrap();
+      store.save_many(tixels.clone()).await.unwrap();
+      let key = tixels[1].cid().to_string();
+      assert_eq!(blobs.get(&key).await.unwrap().as_deref(), Some(&tixels[1].bytes()[..]));
+      let query = sql!(store.db, "SELECT length(data) AS size FROM Tixels WHERE cid = ?1", tixels[1].cid().to_bytes());
+      assert_eq!(store.db.first::<u64>(query, Some("size")).await.unwrap(), Some(0));
+      assert_eq!(store.get_tixel(&tixels[1].cid()).await.unwrap(), *tixels[1].tixel());
+      store.remove(&tixels[1].cid()).await.unwrap();
+      assert!(blobs.get(&key).await.unwrap().is_none());
+      assert!(blobs.get(&tixels[0].cid().to_string()).await.unwrap().is_some());
+    });
+  }
+
+  #[test]
+  fn purging_staged_tixels_keeps_blobs_of_stored_ones() {
+    use crate::blob_store::MemoryBlobStore;
+    let blobs = Arc::new(MemoryBlobStore::default());
+    let store = D1Store::with_backend(test_backend()).with_blob_store(blobs.clone(), 0);
+    let builder = builder();
+    let strand = builder.build_strand().done().unwrap();
+    let tixels = chain(&builder, &strand, 3);
+    block_on(async {
+      store.save(strand.clone()).await.unwrap();
+      store.save_tixel(&tixels[0]).await.unwrap();
+      assert_eq!(store.save_tixel(&tixels[2]).await.unwrap(), Outcome::Staged);
+      store.db.run(sql!(store.db, "UPDATE PendingTixels SET expires_at = datetime('now', '-1 hours')")).await.unwrap();
+      // stored again directly, under the same blob key as the expired staged copy
+      store.save_tixel(&tixels[1]).await.unwrap();
+      store.save_tixel(&tixels[2]).await.unwrap();
+      assert_eq!(store.purge_pending(&strand.cid(), true).await.unwrap(), 1);
+      assert_eq!(store.get_tixel(&tixels[2].cid()).await.unwrap(), *tixels[2].tixel());
+    });
+  }
+
+  #[test]
+  fn saves_the_twines_before_a_refusal() {
+    let store = D1Store::with_backend(test_backend());
+    let builder = builder();
+    let strand = builder.build_strand().done().unwrap();
+    let tixels = chain(&builder, &strand, 2);
+    let unknown = builder.build_strand().done().unwrap();
+    let stray = chain(&builder, &unknown, 3).pop().unwrap();
+    block_on(async {
+      let twines = vec![strand.clone().into(), tixels[0].clone().into(), tixels[1].clone().into(), stray.into()];
+      let report = store.save_batch(twines).await.unwrap();
+      let outcomes: Vec<Outcome> = report.outcomes.iter().map(|(_, o)| *o).collect();
+      assert_eq!(outcomes, vec![Outcome::Saved, Outcome::Saved, Outcome::Saved, Outcome::Refused("unknown_strand")]);
+      assert!(matches!(report.refusal, Some(WriteError::UnknownStrand)));
+      assert_eq!(store.latest_tixel(&strand.cid()).await.unwrap(), *tixels[1].tixel());
+    });
+  }
+
+  #[test]
+  fn skips_only_the_tixels_after_a_refusal_in_their_strand() {
+    let store = D1Store::with_backend(test_backend());
+    let builder = builder();
+    let strand = builder.build_strand().done().unwrap();
+    let other = builder.build_strand().done().unwrap();
+    let tixels = chain(&builder, &strand, 1);
+    let fork = builder.build_first(strand.clone()).payload(ipld!({ "n": "fork" })).done().unwrap();
+    let after_fork = builder.build_next(&fork).done().unwrap();
+    let others = chain(&builder, &other, 2);
+    block_on(async {
+      store.save(strand.clone()).await.unwrap();
+      store.save(other.clone()).await.unwrap();
+      store.save_tixel(&tixels[0]).await.unwrap();
+      let twines = vec![fork.into(), after_fork.into(), others[0].clone().into(), others[1].clone().into()];
+      let report = store.save_batch(twines).await.unwrap();
+      let outcomes: Vec<Outcome> = report.outcomes.iter().map(|(_, o)| *o).collect();
+      assert_eq!(outcomes, vec![Outcome::Refused("fork"), Outcome::Skipped, Outcome::Saved, Outcome::Saved]);
+      assert!(matches!(report.refusal, Some(WriteError::Fork)));
+      assert_eq!(store.latest_tixel(&other.cid()).await.unwrap(), *others[1].tixel());
+
+      // a refusal carries over to later batches of a stream
+      let more = chain(&builder, &other, 4);
+      let mut refused = RefusedStrands::default();
+      refused.refuse(&AnyTwine::from(more[1].clone()));
+      let report = store.save_batch_refusing(vec![more[2].clone().into(), more[3].clone().into()], &mut refused).await.unwrap();
+      assert!(report.outcomes.iter().all(|(_, o)| *o == Outcome::Skipped));
+    });
+  }
+
+  #[test]
+  fn drops_expired_staged_tixels_and_caps_staging() {
+    let store = D1Store::with_backend(test_backend());
+    let builder = builder();
+    let strand = builder.build_strand().done().unwrap();
+    let tixels = chain(&builder, &strand, 4);
+    block_on(async {
+      store.save(strand.clone()).await.unwrap();
+      store.save_tixel(&tixels[0]).await.unwrap();
+      assert_eq!(store.save_tixel(&tixels[2]).await.unwrap(), Outcome::Staged);
+      store.db.run(sql!(store.db, "UPDATE PendingTixels SET expires_at = datetime('now', '-1 hours')")).await.unwrap();
+      assert_eq!(store.save_tixel(&tixels[3]).await.unwrap(), Outcome::Staged);
+      let pending = store.pending_tixels(&strand.cid()).await.unwrap();
+      assert_eq!(pending.iter().map(|p| p.index).collect::<Vec<_>>(), vec![3]);
+
+      // fill staging with placeholder rows
+      let fill = sql!(
+        store.db,
+        "WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < ?1)
+        INSERT INTO PendingTixels (cid, strand, idx, data, size, expires_at)
+        SELECT randomblob(36), 1, 100 + i, x'', 0, datetime('now', '+1 hours') FROM n;",
+        MAX_PENDING_PER_STRAND - 1
+      );
+      store.db.run(fill).await.unwrap();
+      assert!(matches!(store.save_tixel(&tixels[2]).await, Err(WriteError::Gap)));
+    });
   }
 }
@@ -1,7 +1,7 @@
 use axum::response::{IntoResponse, Response};
 use http::StatusCode;
 use twine_protocol::{prelude::{ResolutionError, StoreError}, twine_lib::errors::{ConversionError, VerificationError}};
-use worker::{console_error, console_log};
+use crate::backend::BackendError;
 
 #[derive(Debug, thiserror::Error)]
 pub enum ApiError {
@@ -11,6 +11,8 @@ pub enum ApiError {
   BadRequestData(String),
   #[error("Server error: {0}")]
   ServerError(#[from] worker::Error),
+  #[error("Database error: {0}")]
+  DatabaseError(#[from] BackendError),
   #[error("Verification error: {0}")]
   VerificationError(#[from] VerificationError),
   #[error("Resolution error: {0}")]
@@ -21,10 +23,14 @@ pub enum ApiError {
   InvalidQuery(String),
   #[error("Api key error: {0}")]
   ApiKeyError(#[from] ApiKeyValidationError),
+  #[error("Write rejected: {0}")]
+  WriteError(WriteError),
   #[error("Not found")]
   NotFound,
-  #[error("Unauthorized")]
-  Unauthorized,
+  #[error("Payload too large")]
+  PayloadTooLarge,
+  #[error("Too many writes to this strand")]
+  TooManyWrites,
 }
 
 impl From<ConversionError> for ApiError {
@@ -33,26 +39,27 @@ impl From<ConversionError> for ApiError {
   }
 }
 
-impl ApiError {
-  pub fn to_response(&self) -> Result<worker::Response, worker::Error> {
-    let (text, status) = self.response_info();
-    if status == 500 {
-      log::error!("API Error: {}", text);
-    } else {
-      log::debug!("API response (code: {}): {}", status, text);
+impl From<WriteError> for ApiError {
+  fn from(e: WriteError) -> Self {
+    match e {
+      WriteError::InvalidSignature(e) => ApiError::VerificationError(e),
+      _ => ApiError::WriteError(e),
     }
-    worker::Response::error(text, status)
   }
+}
 
+impl ApiError {
   pub fn response_info(&self) -> (String, u16) {
     match self {
       ApiError::ServerError(e) => (e.to_string(), 500),
-      ApiError::VerificationError(e) => (e.to_string(), 500),
+      ApiError::DatabaseError(e) => (e.to_string(), 500),
+      ApiError::VerificationError(e) => (e.to_string(), 400),
       ApiError::InvalidQuery(e) => (e.to_string(), 400),
       ApiError::NotFound => ("Not found".into(), 404),
       ApiError::Corrupted(e) => (e.to_string(), 500),
       ApiError::BadRequestData(e) => (e.to_string(), 400),
-      ApiError::Unauthorized => ("Unauthorized".into(), 401),
+      ApiError::PayloadTooLarge => ("Payload too large".into(), 413),
+      ApiError::TooManyWrites => ("Too many writes to this strand".into(), 429),
       ApiError::ResolutionError(e) => match e {
         ResolutionError::NotFound => ("Not found".into(), 404),
         _ => (e.to_string(), 500),
@@ -67,10 +74,12 @@ impl ApiError {
       ApiError::ApiKeyError(e) => match e {
         ApiKeyValidationError::InvalidKey => ("Invalid API key".into(), 401),
         ApiKeyValidationError::ExpiredKey => ("Expired API key".into(), 401),
+        ApiKeyValidationError::OutOfScope => ("API key does not allow this".into(), 403),
         ApiKeyValidationError::DatabaseError(_) => {
           ("Server error".into(), 500)
         }
       },
+      ApiError::WriteError(e) => (e.to_string(), e.status()),
     }
   }
 }
@@ -84,10 +93,100 @@ impl IntoResponse for ApiError {
     } else {
       log::debug!("API response (code: {}): {}", code, msg);
     }
-    (code, msg).into_response()
+    match self {
+      ApiError::WriteError(e) => (code, axum::Json(ErrorBody {
+        code: e.code(),
+        message: msg,
+      })).into_response(),
+      _ => (code, msg).into_response(),
+    }
   }
 }
 
+/// Error body for responses that carry a machine-readable code
+#[derive(Debug, serde::Serialize)]
+pub struct ErrorBody {
+  pub code: &'static str,
+  pub message: String,
+}
+
+impl From<&WriteError> for ErrorBody {
+  fn from(e: &WriteError) -> Self {
+    ErrorBody { code: e.code(), message: e.to_string() }
+  }
+}
+
+/// Reasons the store refused to change a strand or tixel
+#[derive(Debug, thiserror::Error)]
+pub enum WriteError {
+  #[error("Tixel is not the latest in its strand")]
+  NotLatest,
+  #[error("Strand is not writable")]
+  NotWritable,
+  #[error("Strand is not stored here")]
+  UnknownStrand,
+  #[error("Strand was removed and can't be stored again")]
+  Removed,
+  #[error("Tixel is not stored here")]
+  UnknownTixel,
+  #[error("Previous tixel is missing")]
+  Gap,
+  #[error("Tixel conflicts with the stored history of its strand")]
+  Fork,
+  #[error("Tixel is already stored")]
+  Duplicate,
+  #[error("Tixel failed verification against its strand: {0}")]
+  InvalidSignature(VerificationError),
+  #[error("Api key does not allow writing this")]
+  Forbidden,
+  #[error(transparent)]
+  Store(#[from] StoreError),
+}
+
+impl WriteError {
+  /// Machine-readable code sent alongside the message
+  pub fn code(&self) -> &'static str {
+    match self {
+      WriteError::NotLatest => "not_latest",
+      WriteError::NotWritable => "not_writable",
+      WriteError::UnknownStrand => "unknown_strand",
+      WriteError::Removed => "removed",
+      WriteError::UnknownTixel => "unknown_tixel",
+      WriteError::Gap => "gap",
+      WriteError::Fork => "fork",
+      WriteError::Duplicate => "duplicate",
+      WriteError::InvalidSignature(_) => "invalid_signature",
+      WriteError::Forbidden => "forbidden",
+      WriteError::Store(_) => "store_error",
+    }
+  }
+
+  pub fn status(&self) -> u16 {
+    match self {
+      WriteError::NotLatest => 409,
+      WriteError::NotWritable => 423,
+      WriteError::UnknownStrand => 404,
+      WriteError::Removed => 410,
+      WriteError::UnknownTixel => 404,
+      WriteError::Gap => 424,
+      WriteError::Fork => 422,
+      WriteError::Duplicate => 409,
+      WriteError::InvalidSignature(_) => 400,
+      WriteError::Forbidden => 403,
+      WriteError::Store(_) => 500,
+    }
+  }
+}
+
+impl From<WriteError> for StoreError {
+  fn from(e: WriteError) -> Self {
+    match e {
+      WriteError::Store(e) => e,
+      WriteError::UnknownTixel => StoreError::Fetching(ResolutionError::NotFound),
+      _ => StoreError::Saving(e.to_string()),
+    }
+  }
+}
 
 #[derive(Debug, thiserror::Error)]
 pub enum ApiKeyValidationError {
@@ -95,8 +194,10 @@ pub enum ApiKeyValidationError {
   InvalidKey,
   #[error("Expired api key")]
   ExpiredKey,
+  #[error("Api key does not allow this")]
+  OutOfScope,
   #[error("Error reading database")]
-  DatabaseError(#[from] worker::Error),
+  DatabaseError(#[from] BackendError),
 }
 
 impl IntoResponse for ApiKeyValidationError {
@@ -108,6 +209,9 @@ impl IntoResponse for ApiKeyValidationError {
       ApiKeyValidationError::ExpiredKey => {
         (StatusCode::UNAUTHORIZED, "Expired API key").into_response()
       }
+      ApiKeyValidationError::OutOfScope => {
+        (StatusCode::FORBIDDEN, "API key does not allow this").into_response()
+      }
       ApiKeyValidationError::DatabaseError(_) => {
         (StatusCode::INTERNAL_SERVER_ERROR, "Server error").into_response()
       }
@@ -1,9 +1,15 @@
+// without the wasm entry points or the server there is nothing to reach the
+// routes from, everything else is checked for dead code
+#![cfg_attr(not(any(target_arch = "wasm32", feature = "server")), allow(dead_code))]
+// the largest error variants come from twine_protocol
+#![allow(clippy::result_large_err)]
+
 use std::{convert::Infallible, str::FromStr};
 
-use access_control::ApiKey;
-// use car::car_to_twines;
+use access_control::{ApiKey, ApiKeyRecord, KeyGrant, Scope};
 // use futures::TryStreamExt;
 use http::StatusCode;
+#[cfg(target_arch = "wasm32")]
 use http_body_util::BodyDataStream;
 use tower_http::cors::CorsLayer;
 use uuid::Uuid;
@@ -11,6 +17,10 @@ use worker::*;
 use twine_protocol::prelude::{unchecked_base::BaseResolver, *};
 
 mod access_control;
+mod app_state;
+use app_state::AppState;
+mod backend;
+mod blob_store;
 mod errors;
 // use errors::*;
 // mod store;
@@ -20,16 +30,21 @@ mod d1_store;
 mod registration;
 use registration::*;
 mod dag_json;
-// mod car;
+mod car;
 mod logging;
 mod admin_routes;
+mod api_routes;
+#[cfg(feature = "server")]
+pub mod server;
 
+#[cfg(target_arch = "wasm32")]
 fn get_max_batch_size(env: &Env) -> u64 {
   env.var("MAX_BATCH_SIZE")
     .map_or(Ok(1000), |s| s.to_string().parse())
     .unwrap_or(1000)
 }
 
+#[cfg(target_arch = "wasm32")]
 async fn proxy_v1(mut req: Request) -> Result<Response> {
   // forward the request to v1 store
   let uri = req.url()?;
@@ -62,12 +77,32 @@ async fn proxy_v1(mut req: Request) -> Result<Response> {
   Ok(response)
 }
 
-fn twine_api_router(db: D1Database, max_query_length: u64, env: Env) -> axum::Router {
-  let store = d1_store::D1Store::new(db);
+/// Whether a key may make a write to the twine api.
+///
+/// Strands are written to `/`, tixels to `/{strand}`, and removing a twine
+/// takes the remove scope for `target`, the strand it is in.
+fn write_allowed(grant: &KeyGrant, method: &http::Method, path: &str, target: Option<&Cid>) -> bool {
+  if method == http::Method::DELETE {
+    return grant.allows(Scope::Remove, target);
+  }
+  match path.trim_start_matches('/') {
+    "" => grant.allows(Scope::WriteStrands, None),
+    "ingest" => grant.allows(Scope::WriteTixels, None) || grant.allows(Scope::WriteStrands, None),
+    path => match Cid::try_from(path) {
+      Ok(strand) => grant.allows(Scope::WriteTixels, Some(&strand)),
+      Err(_) => grant.allows(Scope::Admin, None),
+    },
+  }
+}
+
+fn twine_api_router(state: AppState) -> axum::Router {
+  let store = state.store();
   let db = store.db.clone();
+  let pepper = state.api_key_pepper.clone();
+  let auth_state = state.clone();
   let options = twine_http_store::server::ApiOptions {
     read_only: false,
-    max_query_length,
+    max_query_length: state.max_batch_size,
     ..twine_http_store::server::ApiOptions::default()
   };
   let api = twine_http_store::server::api(store, options);
@@ -86,33 +121,91 @@ fn twine_api_router(db: D1Database, max_query_length: u64, env: Env) -> axum::Ro
     });
 
   axum::Router::new()
+    .merge(api_routes::strands::router().with_state(state.clone()))
+    .merge(api_routes::ingest::router().with_state(state.clone()))
     .fallback_service(tower_service)
-    .layer(axum::middleware::from_fn(move |headers: axum::http::HeaderMap, req: http::Request<axum::body::Body>, next: axum::middleware::Next| {
+    .layer(axum::middleware::from_fn({
+      let state = state.clone();
+      move |req: axum::extract::Request, next: axum::middleware::Next| {
+        api_routes::strands::remove_twines(state.clone(), req, next)
+      }
+    }))
+    .layer(axum::middleware::from_fn({
+      let state = state.clone();
+      move |req: axum::extract::Request, next: axum::middleware::Next| {
+        api_routes::strands::save_twines(state.clone(), req, next)
+      }
+    }))
+    .layer(axum::middleware::from_fn(move |headers: axum::http::HeaderMap, mut req: http::Request<axum::body::Body>, next: axum::middleware::Next| {
       let db = db.clone();
+      let pepper = pepper.clone();
+      let state = auth_state.clone();
       async move {
         if req.method() == http::Method::GET || req.method() == http::Method::HEAD {
           return Ok(next.run(req).await);
         }
-        let auth = headers.get("authorization").ok_or((StatusCode::UNAUTHORIZED, "Unauthorized".to_string()))?;
+        let auth = match headers.get("authorization") {
+          Some(auth) => auth,
+          // tixels signed by an approved strand don't need a key
+          None if state.signed_writes => return Ok(api_routes::strands::signed_write(state, req, next).await),
+          None => return Err((StatusCode::UNAUTHORIZED, "Unauthorized".to_string())),
+        };
         if !auth.to_str().unwrap_or_default().starts_with("ApiKey ") {
           return Err((StatusCode::UNAUTHORIZED, "Unauthorized".to_string()));
         }
         let api_key = auth.to_str().unwrap_or_default().trim_start_matches("ApiKey ");
         let api_key = ApiKey::from_str(api_key).map_err(|_| (StatusCode::UNAUTHORIZED, "Invalid Api Key".to_string()))?;
-        if let Err(e) = send::SendFuture::new(api_key.validate(&db)).await {
+        let grant = match send::SendFuture::new(api_key.validate(db.as_ref(), pepper.as_deref())).await {
+          Ok(grant) => grant,
+          Err(e) => {
+            use axum::response::IntoResponse;
+            return Ok(e.into_response());
+          },
+        };
+        let target = match (req.method(), Cid::try_from(req.uri().path().trim_start_matches('/'))) {
+          (&http::Method::DELETE, Ok(cid)) => {
+            let store = state.store();
+            match send::SendFuture::new(store.strand_of(&cid)).await {
+              Ok(strand) => strand,
+              Err(e) => {
+                use axum::response::IntoResponse;
+                return Ok(errors::ApiError::from(e).into_response());
+              },
+            }
+          },
+          _ => None,
+        };
+        if !write_allowed(&grant, req.method(), req.uri().path(), target.as_ref()) {
           use axum::response::IntoResponse;
-          return Ok(e.into_response());
+          return Ok(errors::ApiKeyValidationError::OutOfScope.into_response());
         }
-        let res = next.run(req).await;
-        Ok(res)
+        let record_id = grant.record_id;
+        // ingest checks each block against the grant
+        req.extensions_mut().insert(grant);
+        let bytes = headers.get(http::header::CONTENT_LENGTH)
+          .and_then(|v| v.to_str().ok()?.parse().ok())
+          .unwrap_or(0);
+        // written after the response, by the handler's usage flush
+        ApiKeyRecord::record_usage(record_id, bytes);
+        Ok(next.run(req).await)
       }
     }))
+    .layer(axum::middleware::from_fn({
+      let state = state.clone();
+      move |req: axum::extract::Request, next: axum::middleware::Next| {
+        api_routes::tixels::time_query(state.clone(), req, next)
+      }
+    }))
+    .layer(axum::middleware::from_fn(move |req: axum::extract::Request, next: axum::middleware::Next| {
+      api_routes::strands::paged_listing(state.clone(), req, next)
+    }))
     .layer(
       CorsLayer::new()
         .allow_origin(tower_http::cors::Any)
     )
 }
 
+#[cfg(target_arch = "wasm32")]
 async fn call_worker_handler<H, F>(handler: H, req: http::Request<axum::body::Body>) -> std::result::Result<http::Response<axum::body::Body>, Infallible>
 where H: FnOnce(Request) -> F + Clone + Send + 'static,
       F: futures::Future<Output = Result<worker::Response>> + 'static,
@@ -130,26 +223,28 @@ where H: FnOnce(Request) -> F + Clone + Send + 'static,
   }
 }
 
-fn router(env: Env) -> axum::Router {
+/// Proxies `/v1` requests to the previous spool
+#[cfg(target_arch = "wasm32")]
+fn v1_router() -> axum::Router {
+  let service = tower::service_fn(move |req: http::Request<axum::body::Body>| {
+    call_worker_handler(proxy_v1, req)
+  });
+
+  axum::Router::new()
+    .route_service("/v1", service)
+    .route_service("/v1{*path}", service)
+}
+
+fn router(state: AppState) -> axum::Router {
   use axum::Json;
   use axum::extract::{State, Path};
   use axum::response::IntoResponse;
   use axum::routing::{get, post};
-  let service = tower::service_fn(move |req: http::Request<axum::body::Body>| {
-    call_worker_handler(proxy_v1, req)
-  });
 
   #[worker::send]
-  async fn registration_route(State(env): State<Env>) -> std::result::Result<http::Response<axum::body::Body>, Infallible> {
-    let url: String = ["https://dummyurl.com/", "register.html"].concat();
-    let res = env.assets("ASSETS")
-      .expect("NEED ASSETS")
-      .fetch(url, None).await;
-    match res {
-      Ok(res) => {
-        let res: http::Response<worker::Body> = res.try_into().unwrap();
-        Ok(res.map(|b| axum::body::Body::from_stream(BodyDataStream::new(b))))
-      },
+  async fn registration_route(State(state): State<AppState>) -> std::result::Result<http::Response<axum::body::Body>, Infallible> {
+    match state.assets.fetch("register.html").await {
+      Ok(res) => Ok(res),
       Err(e) => {
         Ok(
           (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
@@ -158,20 +253,46 @@ fn router(env: Env) -> axum::Router {
     }
   }
 
+  /// Whether a registration was made with a key that may skip approval
+  async fn registered_by_key(state: &AppState, headers: &axum::http::HeaderMap) -> std::result::Result<bool, (axum::http::StatusCode, String)> {
+    let auth = match headers.get(http::header::AUTHORIZATION) {
+      Some(auth) => auth,
+      None => return Ok(false),
+    };
+    let api_key = auth.to_str().ok()
+      .and_then(|auth| auth.strip_prefix("ApiKey "))
+      .and_then(|key| ApiKey::from_str(key).ok())
+      .ok_or((StatusCode::UNAUTHORIZED, "Invalid Api Key".to_string()))?;
+    let grant = api_key.validate(state.db.as_ref(), state.api_key_pepper.as_deref()).await
+      .map_err(|e| match e {
+        errors::ApiKeyValidationError::DatabaseError(_) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+        _ => (StatusCode::UNAUTHORIZED, e.to_string()),
+      })?;
+    if !grant.allows(Scope::Register, None) {
+      return Err((StatusCode::FORBIDDEN, errors::ApiKeyValidationError::OutOfScope.to_string()));
+    }
+    Ok(true)
+  }
+
   #[worker::send]
-  async fn register_strand(State(env): State<Env>, Json(reg): Json<RegistrationRequest>) -> std::result::Result<Json<RegistrationRecordJson>, (axum::http::StatusCode, String)> {
-    let store = d1_store::D1Store::new(env.d1("DB").map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to get DB".to_string()))?);
-    let db = &store.db;
+  async fn register_strand(State(state): State<AppState>, headers: axum::http::HeaderMap, Json(reg): Json<RegistrationRequest>) -> std::result::Result<Json<RegistrationRecordJson>, (axum::http::StatusCode, String)> {
+    let store = state.store();
+    let db = store.db.as_ref();
 
     let strand = reg.strand.clone().unpack();
 
+    // check if the strand has been removed from the spool
+    if let Ok(true) = store.is_tombstoned(&strand.cid()).await {
+      return Err((StatusCode::GONE, "Strand has been removed".to_string()));
+    }
+
     // check if the strand is already registered
     if let Ok(true) = store.has_strand(&strand.cid()).await {
       return Err((StatusCode::CONFLICT, "Strand already registered".to_string()));
     }
 
-    // check if we're accepting all
-    if env.var("ACCEPT_ALL_STRANDS").map(|s| s.to_string()).unwrap_or("false".to_string()) == "true" {
+    // check if we're accepting all, or the key it came with may register
+    if state.accept_all_strands || registered_by_key(&state, &headers).await? {
       let record = RegistrationRecord::new_preapproved(reg.email, strand.cid(), strand.clone());
       record.save(db).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
       return match store.save(strand).await {
@@ -202,18 +323,13 @@ fn router(env: Env) -> axum::Router {
   }
 
   #[worker::send]
-  async fn check_registration(Path(receipt_id): Path<String>, State(env): State<Env>) -> std::result::Result<Json<RegistrationRecordJson>, (axum::http::StatusCode, &'static str)> {
+  async fn check_registration(Path(receipt_id): Path<String>, State(state): State<AppState>) -> std::result::Result<Json<RegistrationRecordJson>, (axum::http::StatusCode, &'static str)> {
     let uuid = match Uuid::try_parse(&receipt_id) {
       Ok(uuid) => uuid,
       Err(_) => return Err((axum::http::StatusCode::BAD_REQUEST, "Invalid receipt id")),
     };
 
-    let db = match env.d1("DB") {
-      Ok(db) => db,
-      Err(_) => return Err((axum::http::StatusCode::INTERNAL_SERVER_ERROR, "Failed to get DB")),
-    };
-
-    let record = match RegistrationRecord::fetch(&db, uuid).await {
+    let record = match RegistrationRecord::fetch(state.db.as_ref(), uuid).await {
       Ok(record) => record,
       Err(_) => {
         return Err((axum::http::StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch record"));
@@ -234,11 +350,10 @@ fn router(env: Env) -> axum::Router {
     .route("/register", get(registration_route))
     .route("/register", post(register_strand))
     .route("/register/{:receipt_id}", get(check_registration))
-    .route_service("/v1", service)
-    .route_service("/v1{*path}", service)
-    .with_state(env)
+    .with_state(state)
 }
 
+#[cfg(target_arch = "wasm32")]
 #[event(start)]
 fn start(){
   console_error_panic_hook::set_once();
@@ -247,12 +362,12 @@ fn start(){
   };
 }
 
-#[cfg(not(feature = "admin"))]
+#[cfg(all(target_arch = "wasm32", not(feature = "admin")))]
 #[event(fetch)]
 async fn fetch(
   req: http::Request<worker::Body>,
   env: Env,
-  _ctx: Context,
+  ctx: Context,
 ) -> Result<http::Response<axum::body::Body>> {
 
   // if let Err(e) = check_auth(&req, &env).await {
@@ -260,15 +375,23 @@ async fn fetch(
   // }
 
   use tower::Service;
-  Ok(
-    axum::Router::new()
-      .with_state(env.clone())
-      .merge(twine_api_router(env.d1("DB")?, get_max_batch_size(&env), env.clone()))
-      .merge(router(env))
-      .as_service()
-      .call(req)
-      .await?
-  )
+  let state = AppState::from_env(&env)?;
+  let (db, usage_interval) = (state.db.clone(), state.key_usage_interval);
+  let res = axum::Router::new()
+    .with_state(env.clone())
+    .merge(twine_api_router(state.clone()))
+    .merge(router(state))
+    .merge(v1_router())
+    .as_service()
+    .call(req)
+    .await?;
+  // keep the isolate alive until api key usage is written
+  ctx.wait_until(async move {
+    if let Err(e) = ApiKeyRecord::flush_usage(db.as_ref(), usage_interval).await {
+      log::warn!("Problem recording api key usage: {}", e);
+    }
+  });
+  Ok(res)
 
   // api.merge(
   //   Router::with_data(store)
@@ -297,7 +420,7 @@ async fn fetch(
   // )
 }
 
-#[cfg(feature = "admin")]
+#[cfg(all(target_arch = "wasm32", feature = "admin"))]
 #[event(fetch)]
 async fn fetch(
   req: http::Request<worker::Body>,
@@ -306,16 +429,15 @@ async fn fetch(
 ) -> Result<http::Response<axum::body::Body>> {
   console_error_panic_hook::set_once();
 
-  use access_control::ApiKeyRecord;
   use errors::ApiKeyValidationError;
 
   #[worker::send]
   async fn gen_test_key(
-    axum::extract::State(env): axum::extract::State<Env>,
+    axum::extract::State(state): axum::extract::State<AppState>,
   ) -> std::result::Result<String, ApiKeyValidationError> {
     let key = ApiKey::generate();
-    ApiKeyRecord::new(&key, "Test Key", None)
-      .save(&env.d1("DB").unwrap())
+    ApiKeyRecord::new(&key, "Test Key", None, state.api_key_pepper.as_deref())
+      .save(state.db.as_ref())
       .await?;
     Ok(key.to_string())
   }
@@ -324,8 +446,8 @@ async fn fetch(
   Ok(
     axum::Router::new()
       .route("/testkey", axum::routing::get(gen_test_key))
-      .nest("/api", admin_routes::api_keys::router())
-      .with_state(env.clone())
+      .nest("/api", admin_routes::router())
+      .with_state(AppState::from_env(&env)?)
       .as_service()
       .call(req)
       .await?
@@ -338,4 +460,313 @@ async fn fetch(
 //     Ok(t) => console_log!("Randomness pulse released: {}", t.cid()),
 //     Err(e) => console_error!("Error handling randomness pulse: {:?}", e),
 //   };
-// }
\ No newline at end of file
+// }
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::sync::Arc;
+  use app_state::Assets;
+  use axum::body::Body;
+  use futures::executor::block_on;
+  use tower::ServiceExt;
+  use twine_protocol::twine_builder::{RingSigner, TwineBuilder};
+
+  fn test_state() -> AppState {
+    AppState {
+      db: Arc::new(backend::test_backend()),
+      max_batch_size: 1000,
+      accept_all_strands: false,
+      timestamp_field: d1_store::DEFAULT_TIMESTAMP_FIELD.to_string(),
+      blobs: None,
+      blob_threshold: d1_store::DEFAULT_BLOB_THRESHOLD,
+      api_key_pepper: Some("pepper".to_string()),
+      key_usage_interval: access_control::DEFAULT_USAGE_INTERVAL_SECONDS,
+      signed_writes: false,
+      signed_write_rate_limit: access_control::DEFAULT_SIGNED_WRITE_RATE_LIMIT,
+      assets: Assets::Directory(Default::default()),
+    }
+  }
+
+  async fn api_key(state: &AppState) -> String {
+    let key = ApiKey::generate();
+    ApiKeyRecord::new(&key, "test", None, state.api_key_pepper.as_deref())
+      .save(state.db.as_ref())
+      .await
+      .unwrap();
+    key.to_string()
+  }
+
+  async fn put(state: &AppState, path: &str, key: Option<&str>, twines: Vec<AnyTwine>) -> (StatusCode, String) {
+    let mut req = http::Request::put(path)
+      .header(http::header::CONTENT_TYPE, "application/vnd.ipld.car");
+    if let Some(key) = key {
+      req = req.header(http::header::AUTHORIZATION, format!("ApiKey {}", key));
+    }
+    let req = req
+      .body(Body::from(api_routes::car_bytes(twines).await))
+      .unwrap();
+    let res = twine_api_router(state.clone()).oneshot(req).await.unwrap();
+    let status = res.status();
+    let body = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+    (status, String::from_utf8_lossy(&body).to_string())
+  }
+
+  #[test]
+  fn writes_answer_refusals_with_their_status() {
+    let state = test_state();
+    let builder = TwineBuilder::<2, _>::new(RingSigner::generate_ed25519().unwrap());
+    let strand = builder.build_strand().done().unwrap();
+    let first = builder.build_first(strand.clone()).done().unwrap();
+    let path = format!("/{}", strand.cid());
+    block_on(async {
+      let key = api_key(&state).await;
+      let (status, body) = put(&state, &path, Some(&key), vec![first.clone().into()]).await;
+      assert_eq!(status, StatusCode::NOT_FOUND);
+      let summary: serde_json::Value = serde_json::from_str(&body).unwrap();
+      assert_eq!(summary["code"], "unknown_strand");
+      assert_eq!(summary["outcomes"][0]["outcome"], "unknown_strand");
+      let (status, _) = put(&state, "/", Some(&key), vec![strand.clone().into()]).await;
+      assert_eq!(status, StatusCode::CREATED);
+      let (status, _) = put(&state, "/", Some(&key), vec![first.clone().into()]).await;
+      assert_eq!(status, StatusCode::BAD_REQUEST);
+      let (status, body) = put(&state, &path, Some(&key), vec![first.clone().into()]).await;
+      assert_eq!(status, StatusCode::CREATED);
+      let summary: serde_json::Value = serde_json::from_str(&body).unwrap();
+      assert_eq!(summary["outcomes"][0]["cid"], first.cid().to_string());
+      assert_eq!(summary["outcomes"][0]["outcome"], "saved");
+    });
+  }
+
+  #[test]
+  fn frozen_strands_refuse_writes_until_unfrozen() {
+    let state = test_state();
+    let builder = TwineBuilder::<2, _>::new(RingSigner::generate_ed25519().unwrap());
+    let strand = builder.build_strand().done().unwrap();
+    let first = builder.build_first(strand.clone()).done().unwrap();
+    let path = format!("/{}", strand.cid());
+    let admin = |method: &str, path: String, body: &'static str| {
+      let req = http::Request::builder()
+        .method(method)
+        .uri(path)
+        .header(http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body))
+        .unwrap();
+      admin_routes::router().with_state(state.clone()).oneshot(req)
+    };
+    block_on(async {
+      let key = api_key(&state).await;
+      let (status, _) = put(&state, "/", Some(&key), vec![strand.clone().into()]).await;
+      assert_eq!(status, StatusCode::CREATED);
+
+      let res = admin("POST", format!("/strands/{}/freeze", strand.cid()), r#"{"reason":"compromised key"}"#).await.unwrap();
+      assert_eq!(res.status(), StatusCode::OK);
+      let status = state.store().strand_status(&strand.cid()).await.unwrap().unwrap();
+      assert!(!status.writable);
+      assert_eq!(status.frozen_reason.as_deref(), Some("compromised key"));
+
+      let (status, body) = put(&state, &path, Some(&key), vec![first.clone().into()]).await;
+      assert_eq!(status, StatusCode::LOCKED);
+      assert!(body.contains("not_writable"));
+      assert!(state.store().get_tixel(&first.cid()).await.is_err());
+
+      let res = admin("POST", format!("/strands/{}/unfreeze", strand.cid()), "").await.unwrap();
+      assert_eq!(res.status(), StatusCode::OK);
+      let (status, _) = put(&state, &path, Some(&key), vec![first.clone().into()]).await;
+      assert_eq!(status, StatusCode::CREATED);
+
+      let other = builder.build_strand().done().unwrap();
+      let res = admin("POST", format!("/strands/{}/freeze", other.cid()), r#"{"reason":"unknown"}"#).await.unwrap();
+      assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    });
+  }
+
+  #[test]
+  fn approved_strands_write_signed_tixels_without_a_key() {
+    use registration::RegistrationRecord;
+    use twine_protocol::twine_lib::ipld_core::ipld;
+    use twine_protocol::twine_lib::{serde_ipld_dagcbor, serde_ipld_dagjson, Ipld};
+    let mut state = test_state();
+    state.signed_writes = true;
+    state.signed_write_rate_limit = 2;
+    let builder = TwineBuilder::<2, _>::new(RingSigner::generate_ed25519().unwrap());
+    let pending = builder.build_strand().done().unwrap();
+    let strand = builder.build_strand().done().unwrap();
+    let first = builder.build_first(strand.clone()).payload(ipld!({ "n": 0 })).done().unwrap();
+    let second = builder.build_next(&first).done().unwrap();
+    let third = builder.build_next(&second).done().unwrap();
+    // the first tixel with its payload changed after signing
+    let forged = {
+      let json = first.tixel().tagged_dag_json().replace(r#""n":0"#, r#""n":1"#);
+      let tagged: Ipld = serde_ipld_dagjson::from_slice(json.as_bytes()).unwrap();
+      let bytes = serde_ipld_dagcbor::to_vec(&tagged.get("data").unwrap()).unwrap();
+      Tixel::from_bytes_unchecked(first.hasher(), bytes).unwrap()
+    };
+    block_on(async {
+      let email: serde_email::Email = "someone@example.com".parse().unwrap();
+      state.store().save(pending.clone()).await.unwrap();
+      state.store().save(strand.clone()).await.unwrap();
+      let (status, _) = put(&state, &format!("/{}", strand.cid()), None, vec![first.clone().into()]).await;
+      assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+      RegistrationRecord::new(email.clone(), pending.clone()).save(state.db.as_ref()).await.unwrap();
+      let tixel = builder.build_first(pending.clone()).done().unwrap();
+      let (status, _) = put(&state, &format!("/{}", pending.cid()), None, vec![tixel.into()]).await;
+      assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+      RegistrationRecord::new_preapproved(email, strand.cid(), strand.clone()).save(state.db.as_ref()).await.unwrap();
+      let path = format!("/{}", strand.cid());
+      for _ in 0..3 {
+        let (status, _) = put(&state, &path, None, vec![forged.clone().into()]).await;
+        assert_ne!(status, StatusCode::CREATED);
+        assert_ne!(status, StatusCode::TOO_MANY_REQUESTS);
+      }
+      let (status, _) = put(&state, &path, None, vec![first.clone().into(), second.clone().into()]).await;
+      assert_eq!(status, StatusCode::CREATED);
+      assert!(state.store().get_tixel(&second.cid()).await.is_ok());
+      let (status, _) = put(&state, &path, None, vec![third.clone().into()]).await;
+      assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    });
+  }
+
+  #[test]
+  fn removed_strands_cant_come_back() {
+    let state = test_state();
+    let builder = TwineBuilder::<2, _>::new(RingSigner::generate_ed25519().unwrap());
+    let strand = builder.build_strand().done().unwrap();
+    block_on(async {
+      let key = api_key(&state).await;
+      state.store().save(strand.clone()).await.unwrap();
+      state.store().remove(&strand.cid()).await.unwrap();
+      let (status, body) = put(&state, "/", Some(&key), vec![strand.clone().into()]).await;
+      assert_eq!(status, StatusCode::GONE);
+      assert!(body.contains("removed"));
+      let req = http::Request::post("/register")
+        .header(http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(format!(r#"{{"email":"someone@example.com","strand":{}}}"#, strand.tagged_dag_json())))
+        .unwrap();
+      let res = router(state.clone()).oneshot(req).await.unwrap();
+      assert_eq!(res.status(), StatusCode::GONE);
+    });
+  }
+
+  #[test]
+  fn removal_is_limited_to_the_keys_strands() {
+    use twine_protocol::twine_lib::store::Store;
+    let state = test_state();
+    let builder = TwineBuilder::<2, _>::new(RingSigner::generate_ed25519().unwrap());
+    let strand = builder.build_strand().done().unwrap();
+    let other = builder.build_strand().done().unwrap();
+    let first = builder.build_first(strand.clone()).done().unwrap();
+    let elsewhere = builder.build_first(other.clone()).done().unwrap();
+    block_on(async {
+      let store = state.store();
+      store.save_many(vec![AnyTwine::from(strand.clone()), other.clone().into()]).await.unwrap();
+      store.save_many(vec![first.clone(), elsewhere.clone()]).await.unwrap();
+      let key = ApiKey::generate();
+      let mut record = ApiKeyRecord::new(&key, "test", None, state.api_key_pepper.as_deref());
+      record.save(state.db.as_ref()).await.unwrap();
+      let strands = vec![strand.cid().to_string()];
+      ApiKeyRecord::set_scopes(state.db.as_ref(), record.id as u64, &[Scope::Remove], Some(&strands)).await.unwrap();
+
+      let delete = |cid: Cid| {
+        let req = http::Request::delete(format!("/{}", cid))
+          .header(http::header::AUTHORIZATION, format!("ApiKey {}", key))
+          .body(Body::empty())
+          .unwrap();
+        twine_api_router(state.clone()).oneshot(req)
+      };
+      assert_eq!(delete(elsewhere.cid()).await.unwrap().status(), StatusCode::FORBIDDEN);
+      assert_eq!(delete(other.cid()).await.unwrap().status(), StatusCode::FORBIDDEN);
+      assert_eq!(delete(first.cid()).await.unwrap().status(), StatusCode::NO_CONTENT);
+      assert_eq!(delete(strand.cid()).await.unwrap().status(), StatusCode::NO_CONTENT);
+      assert!(store.get_tixel(&elsewhere.cid()).await.is_ok());
+    });
+  }
+
+  #[test]
+  fn exports_a_strand_as_a_car() {
+    use futures::StreamExt;
+    use twine_protocol::twine_lib::store::Store;
+    let state = test_state();
+    let builder = TwineBuilder::<2, _>::new(RingSigner::generate_ed25519().unwrap());
+    let strand = builder.build_strand().done().unwrap();
+    let first = builder.build_first(strand.clone()).done().unwrap();
+    let second = builder.build_next(&first).done().unwrap();
+    block_on(async {
+      let store = state.store();
+      store.save(strand.clone()).await.unwrap();
+      store.save_many(vec![first.clone(), second.clone()]).await.unwrap();
+      let req = http::Request::get(format!("/export/{}", strand.cid())).body(Body::empty()).unwrap();
+      let res = twine_api_router(state.clone()).oneshot(req).await.unwrap();
+      assert_eq!(res.status(), StatusCode::OK);
+      let bytes = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+      let reader = car::car_reader(&bytes[..]).await.unwrap();
+      let cids: Vec<Cid> = car::car_twines(reader)
+        .map(|block| block.unwrap().1.unwrap().cid())
+        .collect()
+        .await;
+      assert_eq!(cids, vec![strand.cid(), first.cid(), second.cid()]);
+    });
+  }
+
+  #[test]
+  fn ingest_skips_only_the_refused_strand() {
+    let state = test_state();
+    let builder = TwineBuilder::<2, _>::new(RingSigner::generate_ed25519().unwrap());
+    let strand = builder.build_strand().done().unwrap();
+    let other = builder.build_strand().done().unwrap();
+    let first = builder.build_first(strand.clone()).done().unwrap();
+    let second = builder.build_next(&first).done().unwrap();
+    let elsewhere = builder.build_first(other.clone()).done().unwrap();
+    let elsewhere_next = builder.build_next(&elsewhere).done().unwrap();
+    block_on(async {
+      let key = ApiKey::generate();
+      let mut record = ApiKeyRecord::new(&key, "test", None, state.api_key_pepper.as_deref());
+      record.save(state.db.as_ref()).await.unwrap();
+      record.strands = Some(vec![strand.cid().to_string()]);
+      ApiKeyRecord::set_scopes(state.db.as_ref(), record.id as u64, &record.scopes, record.strands.as_deref()).await.unwrap();
+
+      let twines: Vec<AnyTwine> = vec![strand.clone().into(), first.clone().into(), elsewhere.into(), second.clone().into(), elsewhere_next.into()];
+      let req = http::Request::post("/ingest")
+        .header(http::header::AUTHORIZATION, format!("ApiKey {}", key))
+        .body(Body::from(api_routes::car_bytes(twines).await))
+        .unwrap();
+      let res = twine_api_router(state.clone()).oneshot(req).await.unwrap();
+      assert_eq!(res.status(), StatusCode::OK);
+      let body = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+      let summary: serde_json::Value = serde_json::from_slice(&body).unwrap();
+      let outcomes: Vec<&str> = summary["outcomes"].as_array().unwrap().iter()
+        .map(|o| o["outcome"].as_str().unwrap())
+        .collect();
+      assert_eq!(outcomes, vec!["saved", "saved", "forbidden", "saved", "skipped"]);
+      assert_eq!(summary["refusal"]["code"], "forbidden");
+      assert!(state.store().get_tixel(&second.cid()).await.is_ok());
+    });
+  }
+
+  #[test]
+  fn ingest_reports_what_came_before_an_unreadable_block() {
+    let state = test_state();
+    let builder = TwineBuilder::<2, _>::new(RingSigner::generate_ed25519().unwrap());
+    let strand = builder.build_strand().done().unwrap();
+    let first = builder.build_first(strand.clone()).done().unwrap();
+    let second = builder.build_next(&first).done().unwrap();
+    block_on(async {
+      let key = api_key(&state).await;
+      let twines: Vec<AnyTwine> = vec![strand.clone().into(), first.clone().into(), second.into()];
+      let mut car = api_routes::car_bytes(twines).await;
+      car.truncate(car.len() - 8);
+      let req = http::Request::post("/ingest")
+        .header(http::header::AUTHORIZATION, format!("ApiKey {}", key))
+        .body(Body::from(car))
+        .unwrap();
+      let res = twine_api_router(state.clone()).oneshot(req).await.unwrap();
+      assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+      let body = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+      let summary: serde_json::Value = serde_json::from_slice(&body).unwrap();
+      assert_eq!(summary["accepted"], 2);
+      assert!(summary["failure"].is_string());
+      assert!(state.store().get_tixel(&first.cid()).await.is_ok());
+    });
+  }
+}
@@ -1,11 +1,17 @@
+#[cfg(target_arch = "wasm32")]
 use std::fmt::Display;
+#[cfg(any(target_arch = "wasm32", feature = "server"))]
 use log::{Level, Log, Metadata, Record};
+#[cfg(target_arch = "wasm32")]
 use serde::Serialize;
+#[cfg(target_arch = "wasm32")]
 use web_sys::console::log_1;
+#[cfg(target_arch = "wasm32")]
 use worker::js_sys::Date;
 
 // TODO: release as library
 
+#[cfg(target_arch = "wasm32")]
 #[derive(Debug, Clone, Serialize)]
 struct LogEntry {
   level: String,
@@ -14,6 +20,7 @@ struct LogEntry {
   timestamp: String,
 }
 
+#[cfg(target_arch = "wasm32")]
 impl LogEntry {
   pub fn new(level: impl Display, target: impl Display, message: impl Display) -> Self {
     Self {
@@ -31,8 +38,10 @@ impl LogEntry {
   }
 }
 
+#[cfg(target_arch = "wasm32")]
 pub struct WebLogger;
 
+#[cfg(target_arch = "wasm32")]
 impl WebLogger {
   pub fn init_with_level(level: Level) -> Result<(), log::SetLoggerError> {
     log::set_logger(&Self)?;
@@ -41,6 +50,7 @@ impl WebLogger {
   }
 }
 
+#[cfg(target_arch = "wasm32")]
 impl Log for WebLogger {
   fn enabled(&self, metadata: &Metadata) -> bool {
     metadata.level() <= log::max_level()
@@ -57,3 +67,37 @@ impl Log for WebLogger {
     // No-op for web console
   }
 }
+
+/// Logs to stderr, for running outside of workers
+#[cfg(feature = "server")]
+pub struct StderrLogger;
+
+#[cfg(feature = "server")]
+impl StderrLogger {
+  pub fn init_with_level(level: Level) -> Result<(), log::SetLoggerError> {
+    log::set_logger(&Self)?;
+    log::set_max_level(level.to_level_filter());
+    Ok(())
+  }
+}
+
+#[cfg(feature = "server")]
+impl Log for StderrLogger {
+  fn enabled(&self, metadata: &Metadata) -> bool {
+    metadata.level() <= log::max_level()
+  }
+
+  fn log(&self, record: &Record) {
+    if self.enabled(record.metadata()) {
+      eprintln!(
+        "{} {} {}: {}",
+        chrono::Utc::now().to_rfc3339(),
+        record.level(),
+        record.target(),
+        record.args()
+      );
+    }
+  }
+
+  fn flush(&self) {}
+}
@@ -1,5 +1,6 @@
 use super::*;
 use serde::{Deserialize, Serialize};
+use crate::backend::{sql, BackendError, SqlBackend, SqlValue, ToSqlValue};
 use serde_email::Email;
 use twine_protocol::twine_lib::twine::Tagged;
 use uuid::Uuid;
@@ -24,10 +25,17 @@ pub enum RegistrationStatus {
   Rejected,
 }
 
+impl ToSqlValue for RegistrationStatus {
+  fn to_sql_value(&self) -> std::result::Result<SqlValue, BackendError> {
+    Ok(SqlValue::Text(format!("{:?}", self)))
+  }
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct RegistrationRecord {
   pub uuid: String,
   pub email: Email,
+  #[serde(deserialize_with = "crate::backend::cid_from_bytes")]
   pub strand_cid: Cid,
   #[serde(with = "serde_bytes")]
   pub strand: Vec<u8>,
@@ -80,57 +88,88 @@ impl RegistrationRecord {
     }
   }
 
-  pub async fn save(&self, db: &D1Database) -> Result<()> {
-    let query = query!(
+  pub async fn save(&self, db: &dyn SqlBackend) -> std::result::Result<(), BackendError> {
+    let query = sql!(
       db,
       "INSERT INTO registrations (uuid, email, status, strand_cid, strand)
       VALUES ($1, $2, $3, $4, $5)",
       self.uuid,
-      self.email,
+      self.email.as_str(),
       self.status,
       self.strand_cid,
       self.strand,
-    )?;
+    );
 
-    query.run().await?;
+    db.run(query).await?;
     Ok(())
   }
 
-  pub async fn set_status(&mut self, db: &D1Database, status: RegistrationStatus) -> Result<()> {
-    let query = query!(
+  #[cfg(test)]
+  pub async fn set_status(&mut self, db: &dyn SqlBackend, status: RegistrationStatus) -> std::result::Result<(), BackendError> {
+    let query = sql!(
       db,
       "UPDATE registrations SET status = $1 WHERE uuid = $2",
       status,
       self.uuid,
-    )?;
+    );
 
-    query.run().await?;
+    db.run(query).await?;
     self.status = status;
 
     Ok(())
   }
 
-  pub async fn fetch(db: &D1Database, uuid: Uuid) -> Result<Option<Self>> {
-    let query = query!(
+  pub async fn fetch(db: &dyn SqlBackend, uuid: Uuid) -> std::result::Result<Option<Self>, BackendError> {
+    let query = sql!(
       db,
       "SELECT * FROM registrations WHERE uuid = $1",
       uuid.to_string(),
-    )?;
+    );
 
-    let result = query.first::<RegistrationRecord>(None).await?;
+    let result = db.first::<RegistrationRecord>(query, None).await?;
     Ok(result)
   }
 
-  pub async fn check_approved(db: &D1Database, strand: &Strand) -> Result<Option<Self>> {
-    let query = query!(
+  pub async fn check_approved(db: &dyn SqlBackend, strand: &Strand) -> std::result::Result<Option<Self>, BackendError> {
+    let query = sql!(
       db,
       "SELECT * FROM registrations WHERE strand_cid = $1 AND status = $2",
       strand.cid().to_bytes(),
       RegistrationStatus::Approved,
-    )?;
+    );
 
-    let result = query.first::<RegistrationRecord>(None).await?;
+    let result = db.first::<RegistrationRecord>(query, None).await?;
     Ok(result)
   }
 }
 
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::backend::test_backend;
+  use futures::executor::block_on;
+  use twine_protocol::twine_builder::{RingSigner, TwineBuilder};
+
+  #[test]
+  fn registrations_are_approved() {
+    let db = test_backend();
+    let builder = TwineBuilder::<2, _>::new(RingSigner::generate_ed25519().unwrap());
+    let strand = builder.build_strand().done().unwrap();
+    let email = Email::from_string("someone@example.com".to_string()).unwrap();
+    block_on(async {
+      let mut record = RegistrationRecord::new(email, strand.clone());
+      record.save(&db).await.unwrap();
+      assert!(RegistrationRecord::check_approved(&db, &strand).await.unwrap().is_none());
+      let uuid = Uuid::parse_str(&record.uuid).unwrap();
+      let fetched = RegistrationRecord::fetch(&db, uuid).await.unwrap().unwrap();
+      assert_eq!(fetched.strand_cid, strand.cid());
+      assert!(matches!(fetched.status, RegistrationStatus::Pending));
+      record.set_status(&db, RegistrationStatus::Approved).await.unwrap();
+      let approved = RegistrationRecord::check_approved(&db, &strand).await.unwrap().unwrap();
+      assert_eq!(approved.uuid, record.uuid);
+      let json = RegistrationRecordJson::try_from(approved).unwrap();
+      assert_eq!(json.strand.unpack(), strand);
+    });
+  }
+}
@@ -0,0 +1,212 @@
+use std::{net::SocketAddr, path::{Path, PathBuf}, sync::Arc};
+use serde::Deserialize;
+
+use crate::access_control::ApiKeyRecord;
+use crate::app_state::{AppState, Assets};
+use crate::blob_store::{BlobStore, FsBlobStore};
+use crate::backend::{sql, BackendError, SqlBackend, SqliteBackend};
+
+/// Configuration for a self hosted spool.
+///
+/// Read from the toml file named by `SPOOL_CONFIG` (default `spool.toml`) if it
+/// exists, then overridden by environment variables.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+  /// `SPOOL_LISTEN`
+  pub listen: SocketAddr,
+  /// `SPOOL_ADMIN_LISTEN`: address for the unauthenticated admin api, off if unset
+  pub admin_listen: Option<SocketAddr>,
+  /// `SPOOL_DATABASE`: path to the sqlite database file
+  pub database: PathBuf,
+  /// `SPOOL_STATIC_DIR`: directory containing `register.html`
+  pub static_dir: PathBuf,
+  /// `SPOOL_MIGRATIONS_DIR`
+  pub migrations_dir: PathBuf,
+  /// `MAX_BATCH_SIZE`
+  pub max_batch_size: u64,
+  /// `ACCEPT_ALL_STRANDS`
+  pub accept_all_strands: bool,
+  /// `TIMESTAMP_FIELD`: payload field holding tixel times
+  pub timestamp_field: String,
+  /// `SPOOL_BLOB_DIR`: directory for large tixel data, kept in the database if unset
+  pub blob_dir: Option<PathBuf>,
+  /// `BLOB_THRESHOLD`: size in bytes above which tixel data goes to `blob_dir`
+  pub blob_threshold: usize,
+  /// `API_KEY_PEPPER`: secret mixed in to api key hashes
+  pub api_key_pepper: Option<String>,
+  /// `API_KEY_USAGE_INTERVAL`: seconds between writes of each api key's usage
+  pub key_usage_interval: i64,
+  /// `SIGNED_WRITES`: let approved strands write tixels without an api key
+  pub signed_writes: bool,
+  /// `SIGNED_WRITE_RATE_LIMIT`: tixels per minute per strand without an api key
+  pub signed_write_rate_limit: u32,
+}
+
+impl Default for ServerConfig {
+  fn default() -> Self {
+    Self {
+      listen: ([127, 0, 0, 1], 8787).into(),
+      admin_listen: None,
+      database: "spool.db".into(),
+      static_dir: "frontend".into(),
+      migrations_dir: "migrations".into(),
+      max_batch_size: 1000,
+      accept_all_strands: false,
+      timestamp_field: crate::d1_store::DEFAULT_TIMESTAMP_FIELD.to_string(),
+      blob_dir: None,
+      blob_threshold: crate::d1_store::DEFAULT_BLOB_THRESHOLD,
+      api_key_pepper: None,
+      key_usage_interval: crate::access_control::DEFAULT_USAGE_INTERVAL_SECONDS,
+      signed_writes: false,
+      signed_write_rate_limit: crate::access_control::DEFAULT_SIGNED_WRITE_RATE_LIMIT,
+    }
+  }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ServerError {
+  #[error("Problem reading config: {0}")]
+  Config(String),
+  #[error("Io error: {0}")]
+  Io(#[from] std::io::Error),
+  #[error("Database error: {0}")]
+  Database(#[from] BackendError),
+  #[error("Blob store error: {0}")]
+  Blobs(#[from] crate::blob_store::BlobError),
+}
+
+impl ServerConfig {
+  pub fn load() -> Result<Self, ServerError> {
+    let path = std::env::var("SPOOL_CONFIG").unwrap_or("spool.toml".to_string());
+    let mut config = match std::fs::read_to_string(&path) {
+      Ok(contents) => toml::from_str(&contents).map_err(|e| ServerError::Config(e.to_string()))?,
+      Err(e) if e.kind() == std::io::ErrorKind::NotFound => ServerConfig::default(),
+      Err(e) => return Err(e.into()),
+    };
+
+    fn var<T: std::str::FromStr>(name: &str) -> Result<Option<T>, ServerError> {
+      match std::env::var(name) {
+        Ok(v) => v.parse().map(Some).map_err(|_| ServerError::Config(format!("Invalid value for {}", name))),
+        Err(_) => Ok(None),
+      }
+    }
+
+    if let Some(v) = var("SPOOL_LISTEN")? { config.listen = v; }
+    if let Some(v) = var("SPOOL_ADMIN_LISTEN")? { config.admin_listen = Some(v); }
+    if let Some(v) = var("SPOOL_DATABASE")? { config.database = v; }
+    if let Some(v) = var("SPOOL_STATIC_DIR")? { config.static_dir = v; }
+    if let Some(v) = var("SPOOL_MIGRATIONS_DIR")? { config.migrations_dir = v; }
+    if let Some(v) = var("MAX_BATCH_SIZE")? { config.max_batch_size = v; }
+    if let Some(v) = var("ACCEPT_ALL_STRANDS")? { config.accept_all_strands = v; }
+    if let Some(v) = var("TIMESTAMP_FIELD")? { config.timestamp_field = v; }
+    if let Some(v) = var("SPOOL_BLOB_DIR")? { config.blob_dir = Some(v); }
+    if let Some(v) = var("BLOB_THRESHOLD")? { config.blob_threshold = v; }
+    if let Some(v) = var("API_KEY_PEPPER")? { config.api_key_pepper = Some(v); }
+    if let Some(v) = var("API_KEY_USAGE_INTERVAL")? { config.key_usage_interval = v; }
+    if let Some(v) = var("SIGNED_WRITES")? { config.signed_writes = v; }
+    if let Some(v) = var("SIGNED_WRITE_RATE_LIMIT")? { config.signed_write_rate_limit = v; }
+    Ok(config)
+  }
+}
+
+pub fn init_logger() {
+  if let Err(e) = crate::logging::StderrLogger::init_with_level(log::Level::Info) {
+    eprintln!("Problem starting logger: {}", e);
+  }
+}
+
+/// Apply any migrations in `dir` that haven't been applied yet.
+///
+/// Tracks applied migrations in the same table wrangler uses for D1.
+pub async fn apply_migrations(db: &SqliteBackend, dir: &Path) -> Result<(), ServerError> {
+  db.execute_batch(
+    "CREATE TABLE IF NOT EXISTS d1_migrations (
+      id INTEGER PRIMARY KEY AUTOINCREMENT,
+      name TEXT UNIQUE,
+      applied_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL
+    );"
+  )?;
+
+  let mut files = std::fs::read_dir(dir)?
+    .filter_map(|entry| entry.ok())
+    .map(|entry| entry.path())
+    .filter(|path| path.extension().is_some_and(|ext| ext == "sql"))
+    .collect::<Vec<_>>();
+  files.sort();
+
+  let backend: &dyn SqlBackend = db;
+  for path in files {
+    let name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+    let applied = backend.first::<u8>(
+      sql!(backend, "SELECT TRUE FROM d1_migrations WHERE name = ?1", name),
+      Some("TRUE")
+    ).await?;
+    if applied.is_some() {
+      continue;
+    }
+    // a migration that fails part way leaves nothing behind, and is tried again
+    db.execute_batch_with(
+      &std::fs::read_to_string(&path)?,
+      &[sql!(backend, "INSERT INTO d1_migrations (name) VALUES (?1)", name)]
+    )?;
+    log::info!("Applied migration {}", name);
+  }
+  Ok(())
+}
+
+/// Run the spool on a local sqlite database until the process is stopped
+pub async fn serve(config: ServerConfig) -> Result<(), ServerError> {
+  let db = SqliteBackend::open(&config.database)?;
+  apply_migrations(&db, &config.migrations_dir).await?;
+  let blobs = match &config.blob_dir {
+    Some(dir) => Some(Arc::new(FsBlobStore::open(dir)?) as Arc<dyn BlobStore>),
+    None => None,
+  };
+
+  let state = AppState {
+    db: Arc::new(db),
+    max_batch_size: config.max_batch_size,
+    accept_all_strands: config.accept_all_strands,
+    timestamp_field: config.timestamp_field.clone(),
+    blobs,
+    blob_threshold: config.blob_threshold,
+    api_key_pepper: config.api_key_pepper.clone(),
+    key_usage_interval: config.key_usage_interval,
+    signed_writes: config.signed_writes,
+    signed_write_rate_limit: config.signed_write_rate_limit,
+    assets: Assets::Directory(config.static_dir.clone()),
+  };
+
+  if let Some(addr) = config.admin_listen {
+    let admin = axum::Router::new()
+      .nest("/api", crate::admin_routes::router())
+      .with_state(state.clone());
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    log::info!("Spool admin api listening on {}", addr);
+    tokio::spawn(async move {
+      if let Err(e) = axum::serve(listener, admin).await {
+        log::error!("Admin api stopped: {}", e);
+      }
+    });
+  }
+
+  let (db, usage_interval) = (state.db.clone(), state.key_usage_interval);
+  let app = axum::Router::new()
+    .merge(crate::twine_api_router(state.clone()))
+    .merge(crate::router(state))
+    .layer(axum::middleware::map_response(move |res: axum::response::Response| {
+      let db = db.clone();
+      tokio::spawn(async move {
+        if let Err(e) = ApiKeyRecord::flush_usage(db.as_ref(), usage_interval).await {
+          log::warn!("Problem recording api key usage: {}", e);
+        }
+      });
+      async move { res }
+    }));
+
+  let listener = tokio::net::TcpListener::bind(config.listen).await?;
+  log::info!("Spool listening on {}", config.listen);
+  axum::serve(listener, app).await?;
+  Ok(())
+}